@@ -1,7 +1,7 @@
-use std::{fs::OpenOptions, io::Write, sync::Mutex};
+use std::{any::{Any, TypeId}, collections::HashMap, fs::OpenOptions, io::Write, sync::{atomic::{AtomicBool, Ordering}, Mutex}, thread, time::{Duration, Instant}};
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use invoker_explore::{invoker_manager::{Invoker, InvokeContext, InvokerManager}, Typed};
+use invoker_explore::{invoker_manager::{ConcurrentInvokerManager, Invoker, InvokeContext, InvokerManager}, provide::{Demand, Provide}, Typed};
 use once_cell::sync::Lazy;
 
 
@@ -24,8 +24,21 @@ static MUTEX_INVOKER_MANAGER: Lazy<Mutex<InvokerManager>> = Lazy::new(|| {
     Mutex::new(manager)
 });
 
+static MUTEX_WITHOUT_IO_INVOKER_MANAGER: Lazy<Mutex<InvokerManager>> = Lazy::new(|| {
+    let mut manager = InvokerManager::new();
+    manager.add_invoker(InvokerBar);
+    Mutex::new(manager)
+});
+
+static CONCURRENT_INVOKER_MANAGER: Lazy<ConcurrentInvokerManager> = Lazy::new(|| {
+    let manager = ConcurrentInvokerManager::new();
+    manager.add_invoker(InvokerBar);
+    manager
+});
+
 static INVOKER_FOO: InvokerFoo = InvokerFoo; 
 
+#[allow(dead_code)]
 static INVOKER_BAR: InvokerBar = InvokerBar;
 
 
@@ -44,7 +57,7 @@ impl Invoker<&str> for InvokerFoo {
     fn invoke(&self, context: &mut InvokeContext, req: &str) -> Self::Res {
         // simulate io operations
         let mut file = OpenOptions::new().append(true).create(true).open("./target/tmp/tmp.log").unwrap();
-        file.write(req.as_bytes());
+        file.write_all(req.as_bytes()).unwrap();
 
         let context_opt = context.get_mut::<LocalContext>();
         assert!(context_opt.is_some());
@@ -72,45 +85,170 @@ impl Invoker<&str> for InvokerBar {
 }
 
 
-fn dyn_invoke(input: &str) {
+fn dyn_invoke(input: &str) -> Result<String, String> {
     let invoker = INVOKER_MANAGER.get::<InvokerFoo>().unwrap();
     let mut context = InvokeContext::new();
     let local_context = LocalContext { req_id: "req_id".to_owned(), times: 0 };
     context.with_context(local_context);
-    invoker.invoke(&mut context, input);
+    invoker.invoke(&mut context, input)
 }
 
-fn dyn_mutex_invoke(input: &str) {
+fn dyn_mutex_invoke(input: &str) -> Result<String, String> {
     let manager = MUTEX_INVOKER_MANAGER.lock().unwrap();
     let invoker = manager.get::<InvokerFoo>().unwrap();
     let mut context = InvokeContext::new();
     let local_context = LocalContext { req_id: "req_id".to_owned(), times: 0 };
     context.with_context(local_context);
-    invoker.invoke(&mut context, input);
+    invoker.invoke(&mut context, input)
 }
 
-fn direct_invoke(input: &str) {
+fn direct_invoke(input: &str) -> Result<String, String> {
     let invoker = &INVOKER_FOO;
     let mut context = InvokeContext::new();
     let local_context = LocalContext { req_id: "req_id".to_owned(), times: 0 };
     context.with_context(local_context);
-    invoker.invoke(&mut context, input);
+    invoker.invoke(&mut context, input)
 }
 
-fn dyn_without_io_invoke(input: &str) {
+fn dyn_without_io_invoke(input: &str) -> Result<String, String> {
     let invoker = WITHOUT_IO_INVOKER_MANAGER.get::<InvokerBar>().unwrap();
     let mut context = InvokeContext::new();
     let local_context = LocalContext { req_id: "req_id".to_owned(), times: 0 };
     context.with_context(local_context);
-    invoker.invoke(&mut context, input);
+    invoker.invoke(&mut context, input)
 }
 
-fn direct_without_io_invoke(input: &str) {
+fn direct_without_io_invoke(input: &str) -> Result<String, String> {
     let invoker = &InvokerBar;
     let mut context = InvokeContext::new();
     let local_context = LocalContext { req_id: "req_id".to_owned(), times: 0 };
     context.with_context(local_context);
-    invoker.invoke(&mut context, input);
+    invoker.invoke(&mut context, input)
+}
+
+/// the `HashMap` backed context `InvokeContext` used before, as the baseline of its storage
+#[derive(Default)]
+struct HashMapContext {
+    data: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl HashMapContext {
+
+    fn with_context<I: 'static + Send + Sync>(&mut self, context: I) {
+        self.data.insert(TypeId::of::<I>(), Box::new(context));
+    }
+
+    fn get_mut<I: 'static>(&mut self) -> Option<&mut I> {
+        self.data.get_mut(&TypeId::of::<I>()).and_then(|a| a.downcast_mut())
+    }
+}
+
+fn hash_map_context(entries: usize) -> usize {
+    let mut context = HashMapContext::default();
+    context.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+    for i in 1..entries {
+        // a few distinct entry types, so contexts of different sizes can be built
+        match i {
+            1 => context.with_context(1u32),
+            2 => context.with_context(String::new()),
+            _ => context.with_context(Vec::<u8>::new()),
+        }
+    }
+    let local_context = context.get_mut::<LocalContext>().unwrap();
+    local_context.times += 1;
+    local_context.times
+}
+
+fn small_map_context(entries: usize) -> usize {
+    let mut context = InvokeContext::new();
+    context.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+    for i in 1..entries {
+        // a few distinct entry types, so contexts of different sizes can be built
+        match i {
+            1 => context.with_context(1u32),
+            2 => context.with_context(String::new()),
+            _ => context.with_context(Vec::<u8>::new()),
+        }
+    }
+    let local_context = context.get_mut::<LocalContext>().unwrap();
+    local_context.times += 1;
+    local_context.times
+}
+
+trait RequestId {
+    fn req_id(&self) -> &str;
+}
+
+impl RequestId for LocalContext {
+    fn req_id(&self) -> &str {
+        &self.req_id
+    }
+}
+
+impl Provide for LocalContext {
+    fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
+        demand.provide_ref::<dyn RequestId>(self).provide_ref::<LocalContext>(self);
+    }
+}
+
+fn typed_get(context: &InvokeContext) -> usize {
+    context.get::<LocalContext>().unwrap().req_id.len()
+}
+
+fn provider_request_ref(context: &InvokeContext) -> usize {
+    context.request_ref::<LocalContext>().unwrap().req_id.len()
+}
+
+fn provider_request_dyn(context: &InvokeContext) -> usize {
+    context.request_ref::<dyn RequestId>().unwrap().req_id().len()
+}
+
+fn mutex_contended_invoke(input: &str) -> Result<String, String> {
+    let mut context = InvokeContext::new();
+    context.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+    let manager = MUTEX_WITHOUT_IO_INVOKER_MANAGER.lock().unwrap();
+    manager.get::<InvokerBar>().unwrap().invoke(&mut context, input)
+}
+
+fn concurrent_contended_invoke(input: &str) -> Result<String, String> {
+    let mut context = InvokeContext::new();
+    context.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+    let invoker = CONCURRENT_INVOKER_MANAGER.get::<InvokerBar>().unwrap();
+    invoker.invoke(&mut context, input)
+}
+
+const CONTENDED_THREADS: u64 = 4;
+
+/// `CONTENDED_THREADS` threads calling `invoke` while one more replaces the invoker every 100 micros
+fn contended(iters: u64, invoke: fn(&str) -> Result<String, String>, replace: fn()) -> Duration {
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| while !stop.load(Ordering::Relaxed) {
+            replace();
+            thread::sleep(Duration::from_micros(100));
+        });
+        let start = Instant::now();
+        let readers = (0..CONTENDED_THREADS).map(|_| s.spawn(move || {
+            for _ in 0..iters / CONTENDED_THREADS {
+                invoke(black_box("input")).unwrap();
+            }
+        })).collect::<Vec<_>>();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let elapsed = start.elapsed();
+        stop.store(true, Ordering::Relaxed);
+        elapsed
+    })
+}
+
+fn contended_benchmark(c: &mut Criterion) {
+    c.bench_function("mutex_contended_invoke", |b| b.iter_custom(|iters| contended(iters, mutex_contended_invoke, || {
+        MUTEX_WITHOUT_IO_INVOKER_MANAGER.lock().unwrap().add_invoker(InvokerBar);
+    })));
+    c.bench_function("concurrent_contended_invoke", |b| b.iter_custom(|iters| contended(iters, concurrent_contended_invoke, || {
+        CONCURRENT_INVOKER_MANAGER.add_invoker(InvokerBar);
+    })));
 }
 
 fn dyn_invoke_benchmark(c: &mut Criterion) {
@@ -133,5 +271,25 @@ fn direct_without_io_invoke_benchmark(c: &mut Criterion) {
     c.bench_function("direct_without_io", |b| b.iter(|| direct_without_io_invoke(black_box("input"))));
 }
 
-criterion_group!(benches, dyn_invoke_benchmark, dyn_mutex_invoke_benchmark, direct_invoke_benchmark, dyn_without_io_invoke_benchmark, direct_without_io_invoke_benchmark);
+fn context_storage_benchmark(c: &mut Criterion) {
+    c.bench_function("context_empty_hash_map", |b| b.iter(|| black_box(HashMapContext::default())));
+    c.bench_function("context_empty_small_map", |b| b.iter(|| black_box(InvokeContext::new())));
+    for entries in [1, 4] {
+        c.bench_function(&format!("context_hash_map_{}", entries), |b| b.iter(|| hash_map_context(black_box(entries))));
+        c.bench_function(&format!("context_small_map_{}", entries), |b| b.iter(|| small_map_context(black_box(entries))));
+    }
+}
+
+fn provider_benchmark(c: &mut Criterion) {
+    let mut typed = InvokeContext::new();
+    typed.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+    let mut provided = InvokeContext::new();
+    provided.with_provider(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+
+    c.bench_function("context_typed_get", |b| b.iter(|| typed_get(black_box(&typed))));
+    c.bench_function("context_provider_request_ref", |b| b.iter(|| provider_request_ref(black_box(&provided))));
+    c.bench_function("context_provider_request_dyn", |b| b.iter(|| provider_request_dyn(black_box(&provided))));
+}
+
+criterion_group!(benches, dyn_invoke_benchmark, dyn_mutex_invoke_benchmark, direct_invoke_benchmark, dyn_without_io_invoke_benchmark, direct_without_io_invoke_benchmark, context_storage_benchmark, provider_benchmark, contended_benchmark);
 criterion_main!(benches);
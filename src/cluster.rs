@@ -0,0 +1,154 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Arc, RwLock};
+
+use crate::{describe::{Describe, Description}, error::{FromInvokerError, InvokerError}, invoker_manager::{InvokeContext, Invoker}, registry::{Endpoint, Registry, Subscription}};
+
+
+struct Member<I> {
+    endpoint: Endpoint,
+    invoker: Arc<I>,
+}
+
+type Members<I> = Arc<RwLock<Arc<Vec<Member<I>>>>>;
+
+
+/// invoker over every endpoint of a [`Registry`], calls are spread round robin
+///
+/// membership changes swap in a new member list, invokers of endpoints that are still
+/// present are reused. a call keeps the member it picked alive until it returns, so
+/// removing an endpoint never drops an in-flight call
+pub struct ClusterInvoker<I> {
+    members: Members<I>,
+    next: AtomicUsize,
+    _subscription: Subscription,
+}
+
+impl<I: Send + Sync + 'static> ClusterInvoker<I> {
+
+    /// `factory` builds the invoker for each endpoint joining the cluster
+    pub fn new<R, F>(registry: &R, factory: F) -> Self
+    where
+        R: Registry + ?Sized,
+        F: Fn(&Endpoint) -> I + Send + Sync + 'static
+    {
+        let members: Members<I> = Arc::new(RwLock::new(Arc::new(Vec::new())));
+        let weak = Arc::downgrade(&members);
+        let subscription = registry.subscribe(Box::new(move |endpoints| {
+            if let Some(members) = weak.upgrade() {
+                let old = members.read().unwrap().clone();
+                let new = endpoints.iter().map(|endpoint| {
+                    let invoker = old.iter()
+                        .find(|m| &m.endpoint == endpoint)
+                        .map(|m| m.invoker.clone())
+                        .unwrap_or_else(|| Arc::new(factory(endpoint)));
+                    Member { endpoint: endpoint.clone(), invoker }
+                }).collect();
+                *members.write().unwrap() = Arc::new(new);
+            }
+        }));
+        Self { members, next: AtomicUsize::new(0), _subscription: subscription }
+    }
+
+    /// endpoints currently in the cluster
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.members.read().unwrap().iter().map(|m| m.endpoint.clone()).collect()
+    }
+
+    fn select(&self) -> Option<Arc<I>> {
+        let members = self.members.read().unwrap().clone();
+        if members.is_empty() {
+            return None;
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % members.len();
+        Some(members[i].invoker.clone())
+    }
+
+}
+
+impl<Req, I> Invoker<Req> for ClusterInvoker<I>
+where
+    I: Invoker<Req> + Send + Sync + 'static,
+    I::Res: FromInvokerError
+{
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        match self.select() {
+            Some(invoker) => invoker.invoke(context, req),
+            None => Self::Res::from_invoker_error(InvokerError::NoEndpoint),
+        }
+    }
+}
+
+/// members are alike, the first one describes the cluster
+impl<I: Describe> Describe for ClusterInvoker<I> {
+
+    fn describe(&self) -> Description {
+        let members = self.members.read().unwrap().clone();
+        members.first().map(|m| m.invoker.describe()).unwrap_or_default().layer("cluster")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, mpsc, Arc}, thread};
+
+    use crate::{error::InvokerError, invoker_manager::{InvokeContext, Invoker}, registry::{Endpoint, StaticRegistry}};
+    use super::ClusterInvoker;
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct EchoInvoker {
+        address: String,
+    }
+
+    impl Drop for EchoInvoker {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Invoker<Option<mpsc::Receiver<()>>> for EchoInvoker {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Option<mpsc::Receiver<()>>) -> Self::Res {
+            if let Some(rx) = req {
+                rx.recv().unwrap();
+            }
+            Ok(self.address.clone())
+        }
+    }
+
+    #[test]
+    fn test_cluster_invoker() {
+        let registry = StaticRegistry::new(vec![Endpoint::new("a:1"), Endpoint::new("b:1")]);
+        let cluster = Arc::new(ClusterInvoker::new(&registry, |e: &Endpoint| EchoInvoker { address: e.address.clone() }));
+        let mut context = InvokeContext::new();
+        assert_eq!("a:1", cluster.invoke(&mut context, None).unwrap());
+        assert_eq!("b:1", cluster.invoke(&mut context, None).unwrap());
+
+        // start a call on b, then remove b while the call is in flight
+        cluster.invoke(&mut context, None).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let cluster_clone = cluster.clone();
+        let in_flight = thread::spawn(move || cluster_clone.invoke(&mut InvokeContext::new(), Some(rx)));
+        while cluster.members.read().unwrap()[1].invoker.address != "b:1" || Arc::strong_count(&cluster.members.read().unwrap()[1].invoker) < 2 {
+            thread::yield_now();
+        }
+        registry.set_endpoints(vec![Endpoint::new("a:1")]);
+        assert_eq!(vec![Endpoint::new("a:1")], cluster.endpoints());
+        assert_eq!(0, DROPPED.load(Ordering::SeqCst));
+
+        tx.send(()).unwrap();
+        assert_eq!("b:1", in_flight.join().unwrap().unwrap());
+        assert_eq!(1, DROPPED.load(Ordering::SeqCst));
+        assert_eq!("a:1", cluster.invoke(&mut context, None).unwrap());
+
+        registry.set_endpoints(vec![]);
+        assert!(matches!(cluster.invoke(&mut context, None), Err(InvokerError::NoEndpoint)));
+    }
+
+}
@@ -0,0 +1,541 @@
+//! invoker stacks described in a TOML or JSON file instead of code
+//!
+//! every entry under `invokers` names a factory registered in [`Factories`], which builds
+//! the invoker from the entry. the invokers are registered in an [`InvokerManager`] with the
+//! entry name as [`Qualifier`]. factories put what they built into the layers the entry
+//! configures with [`InvokerConfig::layered`]
+//!
+//! ```toml
+//! [invokers.users]
+//! factory = "json_rpc"
+//! endpoints = ["10.0.0.1:8080", "10.0.0.2:8080"]
+//! timeout_ms = 500
+//! load_balancer = "round_robin"
+//! retry = { max_attempts = 3, backoff_ms = 20 }
+//! circuit_breaker = { failure_rate_threshold = 0.3 }
+//! ```
+
+use std::{collections::{BTreeMap, HashMap}, fs, io, marker::PhantomData, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    describe::{Describe, Description},
+    error::InvokerError,
+    invoker::invoker5::{MethodDef, MethodDefInfo, RetryPolicy},
+    invoker_manager::{InvokeContext, Invoker, InvokerManager, Qualifier},
+    layer::{circuit_breaker::{CircuitBreaker, CircuitBreakerConfig}, retry::Retry, timeout::Timeout},
+};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("config io error")]
+    IoError(#[from] io::Error),
+    #[error("invalid config at `{path}`: {message}")]
+    Invalid { path: String, message: String },
+    #[error("unknown factory `{factory}` at `{path}`")]
+    UnknownFactory { path: String, factory: String },
+    #[error("failed to build invoker at `{path}`")]
+    Build { path: String, #[source] source: anyhow::Error },
+}
+
+impl ConfigError {
+
+    fn invalid<E: std::fmt::Display>(e: serde_path_to_error::Error<E>) -> Self {
+        ConfigError::Invalid { path: e.path().to_string(), message: e.into_inner().to_string() }
+    }
+
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagerConfig {
+    #[serde(default)]
+    pub invokers: BTreeMap<String, InvokerConfig>,
+}
+
+impl ManagerConfig {
+
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        serde_path_to_error::deserialize(toml::Deserializer::new(content)).map_err(ConfigError::invalid)
+    }
+
+    pub fn from_json(content: &str) -> Result<Self, ConfigError> {
+        let mut deserializer = serde_json::Deserializer::from_str(content);
+        serde_path_to_error::deserialize(&mut deserializer).map_err(ConfigError::invalid)
+    }
+
+    /// TOML for `.toml` files, JSON otherwise
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&content),
+            _ => Self::from_json(&content),
+        }
+    }
+
+}
+
+
+/// one invoker stack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InvokerConfig {
+    /// name of the factory building this invoker
+    pub factory: String,
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    pub codec: Option<String>,
+    pub protocol: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub retry: Option<RetryPolicy>,
+    pub circuit_breaker: Option<CircuitBreakerSection>,
+    /// how a factory spreads calls over `endpoints`
+    pub load_balancer: Option<LoadBalancer>,
+    /// anything else the factory understands
+    #[serde(default)]
+    pub options: BTreeMap<String, serde_json::Value>,
+}
+
+impl InvokerConfig {
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    /// `info` with the timeout and retry policy of the entry, where it has them
+    pub fn method_info(&self, info: &MethodDefInfo) -> MethodDefInfo {
+        MethodDefInfo {
+            timeout: self.timeout().or(info.timeout),
+            retry: self.retry.clone().or_else(|| info.retry.clone()),
+            ..info.clone()
+        }
+    }
+
+    /// `invoker` of `method` in the layers the entry configures, see [`Layered`]
+    pub fn layered<M: MethodDef, I>(&self, method: &M, invoker: I) -> Layered<M, I> {
+        let info = self.method_info(method.get_method_def_info());
+        let invoker = match &self.circuit_breaker {
+            Some(section) => Breaker::On(CircuitBreaker::new(invoker, section.to_config())),
+            None => Breaker::Off(invoker),
+        };
+        let retry = Retry::new(ConfiguredMethod::new(info.clone()), invoker);
+        Layered { inner: Timeout::new(ConfiguredMethod::new(info), retry) }
+    }
+
+}
+
+
+/// a method with the info of its config entry
+struct ConfiguredMethod<M> {
+    info: MethodDefInfo,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M> ConfiguredMethod<M> {
+
+    fn new(info: MethodDefInfo) -> Self {
+        Self { info, _marker: PhantomData }
+    }
+
+}
+
+impl<M: MethodDef> MethodDef for ConfiguredMethod<M> {
+
+    const NAME: &'static str = M::NAME;
+
+    type Request = M::Request;
+
+    type Response = M::Response;
+
+    fn get_method_def_info(&self) -> &MethodDefInfo {
+        &self.info
+    }
+}
+
+enum Breaker<I> {
+    On(CircuitBreaker<I>),
+    Off(I),
+}
+
+impl<Req, I, T> Invoker<Req> for Breaker<I>
+where
+    I: Invoker<Req, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        match self {
+            Breaker::On(breaker) => breaker.invoke(context, req),
+            Breaker::Off(invoker) => invoker.invoke(context, req),
+        }
+    }
+}
+
+impl<I: Describe> Describe for Breaker<I> {
+
+    fn describe(&self) -> Description {
+        match self {
+            Breaker::On(breaker) => breaker.describe(),
+            Breaker::Off(invoker) => invoker.describe(),
+        }
+    }
+}
+
+/// an invoker in the layers of its config entry, built by [`InvokerConfig::layered`]
+///
+/// outermost first, a [`Timeout`] by `timeout_ms` and a [`Retry`] by `retry`, each falling
+/// back to the [`MethodDefInfo`] of `M`, then a [`CircuitBreaker`] if the entry has a
+/// `circuit_breaker` section
+pub struct Layered<M, I> {
+    inner: Timeout<ConfiguredMethod<M>, Retry<ConfiguredMethod<M>, Breaker<I>>>,
+}
+
+impl<M, I, T> Invoker<M::Request> for Layered<M, I>
+where
+    M: MethodDef,
+    M::Request: Clone,
+    I: Invoker<M::Request, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        self.inner.invoke(context, req)
+    }
+}
+
+impl<M, I: Describe> Describe for Layered<M, I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe()
+    }
+}
+
+
+/// overrides of the [`CircuitBreakerConfig`] defaults
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerSection {
+    pub failure_rate_threshold: Option<f64>,
+    pub slow_call_rate_threshold: Option<f64>,
+    pub slow_call_duration_ms: Option<u64>,
+    pub window_size: Option<usize>,
+    pub minimum_calls: Option<usize>,
+    pub open_duration_ms: Option<u64>,
+    pub half_open_calls: Option<usize>,
+}
+
+impl CircuitBreakerSection {
+
+    pub fn to_config(&self) -> CircuitBreakerConfig {
+        let default = CircuitBreakerConfig::default();
+        CircuitBreakerConfig {
+            failure_rate_threshold: self.failure_rate_threshold.unwrap_or(default.failure_rate_threshold),
+            slow_call_rate_threshold: self.slow_call_rate_threshold.unwrap_or(default.slow_call_rate_threshold),
+            slow_call_duration: self.slow_call_duration_ms.map(Duration::from_millis).unwrap_or(default.slow_call_duration),
+            window_size: self.window_size.unwrap_or(default.window_size),
+            minimum_calls: self.minimum_calls.unwrap_or(default.minimum_calls),
+            open_duration: self.open_duration_ms.map(Duration::from_millis).unwrap_or(default.open_duration),
+            half_open_calls: self.half_open_calls.unwrap_or(default.half_open_calls),
+        }
+    }
+
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancer {
+    /// the way of a [`ClusterInvoker`](crate::cluster::ClusterInvoker)
+    RoundRobin,
+}
+
+
+type Register = Box<dyn FnOnce(&mut InvokerManager) + Send>;
+
+type Build = Box<dyn Fn(&InvokerConfig, Qualifier) -> anyhow::Result<Register> + Send + Sync>;
+
+struct Factory {
+    build: Build,
+    remove: fn(&mut InvokerManager, &Qualifier),
+}
+
+/// named factories the invokers of a config are built with
+#[derive(Default)]
+pub struct Factories {
+    factories: HashMap<String, Factory>,
+}
+
+impl Factories {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<Req, I, F>(&mut self, name: impl Into<String>, factory: F)
+    where
+        I: Invoker<Req> + 'static + Send + Sync,
+        F: Fn(&InvokerConfig) -> anyhow::Result<I> + Send + Sync + 'static
+    {
+        let build = move |config: &InvokerConfig, qualifier: Qualifier| {
+            let invoker = factory(config)?;
+            let register: Register = Box::new(move |manager: &mut InvokerManager| {
+                manager.add_qualified(qualifier, invoker);
+            });
+            Ok(register)
+        };
+        let remove = |manager: &mut InvokerManager, qualifier: &Qualifier| {
+            manager.remove_qualified::<I>(qualifier);
+        };
+        self.factories.insert(name.into(), Factory { build: Box::new(build), remove });
+    }
+
+}
+
+
+/// what a [`ConfiguredManager::reload`] did, by entry name
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+
+/// manager built from a [`ManagerConfig`], keeping it to rebuild only what changes
+pub struct ConfiguredManager {
+    manager: InvokerManager,
+    config: ManagerConfig,
+    factories: Factories,
+}
+
+impl ConfiguredManager {
+
+    pub fn new(config: ManagerConfig, factories: Factories) -> Result<Self, ConfigError> {
+        let mut configured = Self { manager: InvokerManager::new(), config: ManagerConfig::default(), factories };
+        configured.reload(config)?;
+        Ok(configured)
+    }
+
+    pub fn manager(&self) -> &InvokerManager {
+        &self.manager
+    }
+
+    pub fn manager_mut(&mut self) -> &mut InvokerManager {
+        &mut self.manager
+    }
+
+    pub fn config(&self) -> &ManagerConfig {
+        &self.config
+    }
+
+    /// apply `config`, building the invokers of new and changed entries and dropping those
+    /// of removed ones. nothing changes if any invoker fails to build
+    pub fn reload(&mut self, config: ManagerConfig) -> Result<ReloadReport, ConfigError> {
+        let mut report = ReloadReport::default();
+        let mut built = Vec::new();
+        for (name, invoker) in &config.invokers {
+            match self.config.invokers.get(name) {
+                Some(old) if old == invoker => {
+                    report.unchanged.push(name.clone());
+                    continue;
+                },
+                Some(_) => report.changed.push(name.clone()),
+                None => report.added.push(name.clone()),
+            }
+            let factory = self.factories.factories.get(&invoker.factory).ok_or_else(|| ConfigError::UnknownFactory {
+                path: format!("invokers.{}.factory", name),
+                factory: invoker.factory.clone(),
+            })?;
+            let register = (factory.build)(invoker, Qualifier::Named(name.clone()))
+                .map_err(|source| ConfigError::Build { path: format!("invokers.{}", name), source })?;
+            built.push(register);
+        }
+        report.removed = self.config.invokers.keys().filter(|name| !config.invokers.contains_key(*name)).cloned().collect();
+
+        // the factory of a changed entry may differ, the old registration goes first
+        for name in report.changed.iter().chain(&report.removed) {
+            let old = &self.config.invokers[name];
+            if let Some(factory) = self.factories.factories.get(&old.factory) {
+                (factory.remove)(&mut self.manager, &Qualifier::Named(name.clone()));
+            }
+        }
+        for register in built {
+            register(&mut self.manager);
+        }
+        self.config = config;
+        Ok(report)
+    }
+
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use serde_json::Value;
+
+    use crate::{context::Deadline, describe::{Describe, Description}, error::{ErrorCode, InvokerError}, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker, Qualifier}, layer::test_method::GetMethod, Typed};
+    use super::{ConfigError, ConfiguredManager, Factories, LoadBalancer, ManagerConfig, ReloadReport};
+
+    struct Endpoints(Vec<String>);
+
+    impl Invoker<()> for Endpoints {
+
+        type Res = Result<Vec<String>, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: ()) -> Self::Res {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn factories(builds: &Arc<AtomicUsize>) -> Factories {
+        let mut factories = Factories::new();
+        let builds = builds.clone();
+        factories.register("static", move |config| {
+            builds.fetch_add(1, Ordering::SeqCst);
+            if config.endpoints.is_empty() {
+                anyhow::bail!("no endpoints");
+            }
+            Ok(Endpoints(config.endpoints.clone()))
+        });
+        factories
+    }
+
+    const CONFIG: &str = r#"
+        [invokers.users]
+        factory = "static"
+        endpoints = ["10.0.0.1:8080"]
+        timeout_ms = 500
+        load_balancer = "round_robin"
+        circuit_breaker = { failure_rate_threshold = 0.3 }
+
+        [invokers.orders]
+        factory = "static"
+        endpoints = ["10.0.0.2:8080"]
+        retry = { max_attempts = 3 }
+    "#;
+
+    fn endpoints(configured: &ConfiguredManager, name: &str) -> Option<Vec<String>> {
+        let qualifier = Qualifier::Named(name.to_owned());
+        let invoker = configured.manager().get_qualified::<Endpoints>(&qualifier)?;
+        Some(invoker.invoke(&mut InvokeContext::new(), ()).unwrap())
+    }
+
+    #[test]
+    fn test_load_and_reload() {
+        let config = ManagerConfig::from_toml(CONFIG).unwrap();
+        let users = &config.invokers["users"];
+        assert_eq!(Some(LoadBalancer::RoundRobin), users.load_balancer);
+        assert_eq!(500, users.timeout().unwrap().as_millis());
+        assert_eq!(0.3, users.circuit_breaker.as_ref().unwrap().to_config().failure_rate_threshold);
+
+        let builds = Arc::new(AtomicUsize::new(0));
+        let mut configured = ConfiguredManager::new(config.clone(), factories(&builds)).unwrap();
+        assert_eq!(2, builds.load(Ordering::SeqCst));
+        assert_eq!(Some(vec!["10.0.0.1:8080".to_owned()]), endpoints(&configured, "users"));
+
+        let mut changed = config;
+        changed.invokers.get_mut("users").unwrap().endpoints.push("10.0.0.3:8080".to_owned());
+        changed.invokers.remove("orders");
+        let report = configured.reload(changed).unwrap();
+        assert_eq!(ReloadReport { changed: vec!["users".to_owned()], removed: vec!["orders".to_owned()], ..ReloadReport::default() }, report);
+        assert_eq!(3, builds.load(Ordering::SeqCst));
+        assert_eq!(2, endpoints(&configured, "users").unwrap().len());
+        assert!(endpoints(&configured, "orders").is_none());
+
+        // a failing build leaves everything as it was
+        let mut broken = configured.config().clone();
+        broken.invokers.get_mut("users").unwrap().endpoints.clear();
+        let err = configured.reload(broken).unwrap_err();
+        assert!(matches!(err, ConfigError::Build { path, .. } if path == "invokers.users"));
+        assert_eq!(2, endpoints(&configured, "users").unwrap().len());
+    }
+
+    #[test]
+    fn test_error_path() {
+        let typo = CONFIG.replace("max_attempts", "max_attempt");
+        match ManagerConfig::from_toml(&typo).unwrap_err() {
+            ConfigError::Invalid { path, .. } => assert_eq!("invokers.orders.retry.max_attempt", path),
+            e => panic!("unexpected {:?}", e),
+        }
+        let json = r#"{"invokers": {"users": {"factory": "static", "load_balancer": "random"}}}"#;
+        match ManagerConfig::from_json(json).unwrap_err() {
+            ConfigError::Invalid { path, .. } => assert_eq!("invokers.users.load_balancer", path),
+            e => panic!("unexpected {:?}", e),
+        }
+
+        let unknown = ManagerConfig::from_json(r#"{"invokers": {"users": {"factory": "grpc"}}}"#).unwrap();
+        let err = ConfiguredManager::new(unknown, Factories::new()).err().unwrap();
+        assert_eq!("unknown factory `grpc` at `invokers.users.factory`", err.to_string());
+    }
+
+    /// fails every call as if no endpoint answered, counting the calls made with a deadline
+    #[derive(Default)]
+    struct Unreachable {
+        calls: AtomicUsize,
+        deadlines: AtomicUsize,
+    }
+
+    impl Invoker<Value> for Unreachable {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, context: &mut InvokeContext, _req: Value) -> Self::Res {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if context.get::<Deadline>().is_some() {
+                self.deadlines.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(InvokerError::NoEndpoint)
+        }
+    }
+
+    impl Describe for Unreachable {
+
+        fn describe(&self) -> Description {
+            Description::default()
+        }
+    }
+
+    #[test]
+    fn test_layered() {
+        let config = ManagerConfig::from_toml(r#"
+            [invokers.users]
+            factory = "unreachable"
+            timeout_ms = 500
+            retry = { max_attempts = 3, backoff_ms = 1 }
+            circuit_breaker = { minimum_calls = 2, window_size = 2 }
+
+            [invokers.orders]
+            factory = "unreachable"
+        "#).unwrap();
+        let users = &config.invokers["users"];
+        assert_eq!(Duration::from_millis(1), users.retry.as_ref().unwrap().backoff);
+
+        let unreachable = Arc::new(Unreachable::default());
+        let layered = users.layered(&GetMethod::new(MethodDefInfo::default()), unreachable.clone());
+        assert_eq!(vec!["timeout", "retry", "circuit_breaker"], layered.describe().layers);
+        let err = layered.invoke(&mut InvokeContext::new(), Value::Null).unwrap_err();
+        // retried until the breaker opened
+        assert_eq!(ErrorCode::CircuitOpen, err.code());
+        assert_eq!(2, unreachable.calls.load(Ordering::SeqCst));
+        assert_eq!(2, unreachable.deadlines.load(Ordering::SeqCst));
+
+        // only the options of the method apply
+        let orders = &config.invokers["orders"];
+        let unreachable = Arc::new(Unreachable::default());
+        let layered = orders.layered(&GetMethod::new(MethodDefInfo::default()), unreachable.clone());
+        assert_eq!(ErrorCode::Unavailable, layered.invoke(&mut InvokeContext::new(), Value::Null).unwrap_err().code());
+        assert_eq!(1, unreachable.calls.load(Ordering::SeqCst));
+        assert_eq!(0, unreachable.deadlines.load(Ordering::SeqCst));
+    }
+
+}
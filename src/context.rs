@@ -0,0 +1,119 @@
+//! well known entries of [`InvokeContext`](crate::invoker_manager::InvokeContext), shared by the layers
+
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+
+use crate::{error::InvokerError, invoker::invoker5::MethodDef};
+
+
+/// the method being invoked
+///
+/// put into the context by the caller, so layers wrapping an invoker which serves
+/// several methods can tell the calls apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Method {
+    name: &'static str,
+}
+
+impl Method {
+
+    pub fn of<M: MethodDef>() -> Self {
+        Self { name: M::NAME }
+    }
+
+    pub fn named(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+}
+
+
+/// point in time after which the caller no longer waits for an answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.0
+    }
+
+}
+
+
+/// string metadata sent along with the request, like headers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    map: HashMap<String, String>,
+}
+
+impl Metadata {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.map.insert(key.into(), value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(|v| v.as_str())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.map.remove(key)
+    }
+
+}
+
+
+/// left in the context when the answer came from a fallback instead of the invoker asked
+#[derive(Debug, Clone)]
+pub struct Degraded {
+    /// the error which triggered the fallback, `None` in a context whose own call was
+    /// answered by the invoker asked, shadowing the `Degraded` of a parent context
+    pub cause: Option<InvokerError>,
+}
+
+impl Degraded {
+
+    pub fn is_degraded(&self) -> bool {
+        self.cause.is_some()
+    }
+}
+
+
+/// skips response caches for this call, the fresh response is still stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheBypass;
+
+
+/// scopes granted to the caller, checked against the `auth_scope` of `MethodDefInfo`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scopes {
+    scopes: HashSet<String>,
+}
+
+impl Scopes {
+
+    pub fn new<S: Into<String>>(scopes: impl IntoIterator<Item = S>) -> Self {
+        Self { scopes: scopes.into_iter().map(Into::into).collect() }
+    }
+
+    pub fn contains(&self, scope: &str) -> bool {
+        self.scopes.contains(scope)
+    }
+
+}
@@ -0,0 +1,263 @@
+//! what is registered in an [`InvokerManager`], as a serializable report for admin endpoints
+//!
+//! every registration shows up with its type name and qualifier. invokers implementing
+//! [`Describe`] and made [`InvokerManager::describable`] add the methods they serve and
+//! their layer stack, layers describe themselves by wrapping the description of their
+//! inner invoker
+
+use std::{any::{type_name, Any, TypeId}, sync::Arc};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::{invoker::invoker5::MethodDef, invoker_manager::{InvokerManager, Qualifier}, schema::schema_of};
+
+
+/// invokers telling what they serve
+pub trait Describe {
+
+    fn describe(&self) -> Description;
+
+}
+
+impl<I: Describe> Describe for Arc<I> {
+
+    fn describe(&self) -> Description {
+        (**self).describe()
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Description {
+    pub methods: Vec<MethodDescription>,
+    /// names of the layers around the invoker, outermost first
+    pub layers: Vec<&'static str>,
+}
+
+impl Description {
+
+    pub fn method<M: MethodDef>(mut self) -> Self {
+        self.methods.push(MethodDescription::of::<M>());
+        self
+    }
+
+    /// a method along with the json schemas of its request and response
+    pub fn method_with_schemas<M>(mut self) -> Self
+    where
+        M: MethodDef,
+        M::Request: JsonSchema,
+        M::Response: JsonSchema,
+    {
+        self.methods.push(MethodDescription::with_schemas::<M>());
+        self
+    }
+
+    /// the description of an invoker wrapped in the layer `name`
+    pub fn layer(mut self, name: &'static str) -> Self {
+        self.layers.insert(0, name);
+        self
+    }
+
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MethodDescription {
+    pub name: &'static str,
+    pub request_type: &'static str,
+    pub response_type: &'static str,
+    pub request_schema: Option<serde_json::Value>,
+    pub response_schema: Option<serde_json::Value>,
+}
+
+impl MethodDescription {
+
+    pub fn of<M: MethodDef>() -> Self {
+        Self {
+            name: M::NAME,
+            request_type: type_name::<M::Request>(),
+            response_type: type_name::<M::Response>(),
+            request_schema: None,
+            response_schema: None,
+        }
+    }
+
+    /// along with the json schemas of request and response
+    pub fn with_schemas<M>() -> Self
+    where
+        M: MethodDef,
+        M::Request: JsonSchema,
+        M::Response: JsonSchema,
+    {
+        Self {
+            request_schema: Some(schema_of::<M::Request>()),
+            response_schema: Some(schema_of::<M::Response>()),
+            ..Self::of::<M>()
+        }
+    }
+
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InvokerReport {
+    pub type_name: &'static str,
+    pub qualifier: Option<Qualifier>,
+    /// `None` unless the type was made describable
+    pub description: Option<Description>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ManagerReport {
+    pub invokers: Vec<InvokerReport>,
+    /// types of the factories registered for injection
+    pub factories: Vec<&'static str>,
+    /// types whose lifecycle is driven by the manager
+    pub managed: Vec<&'static str>,
+}
+
+
+pub(crate) type Describer = fn(&dyn Any) -> Option<Description>;
+
+fn describe_any<I: Describe + 'static>(invoker: &dyn Any) -> Option<Description> {
+    invoker.downcast_ref::<I>().map(|i| i.describe())
+}
+
+
+impl InvokerManager {
+
+    /// describe every registration of `I` in [`InvokerManager::report`], qualified ones too
+    pub fn describable<I: Describe + 'static>(&mut self) {
+        self.describers.insert(TypeId::of::<I>(), describe_any::<I>);
+    }
+
+    pub fn report(&self) -> ManagerReport {
+        let mut invokers = self.registrations().map(|(id, type_name, qualifier, invoker)| {
+            let description = self.describers.get(&id).and_then(|describe| describe(invoker));
+            InvokerReport { type_name, qualifier: qualifier.cloned(), description }
+        }).collect::<Vec<_>>();
+        invokers.sort_by(|a, b| (a.type_name, format!("{:?}", a.qualifier)).cmp(&(b.type_name, format!("{:?}", b.qualifier))));
+
+        let mut factories = self.lazy.values().map(|(name, _)| *name).collect::<Vec<_>>();
+        factories.sort();
+        ManagerReport { invokers, factories, managed: self.managed_names().collect() }
+    }
+
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::{error::InvokerError, invoker::invoker5::{JsonDecoder, JsonEncoder, Message, MethodDef, MethodDefInfo}, invoker_manager::{InvokeContext, Invoker, InvokerManager}, layer::circuit_breaker::{CircuitBreaker, CircuitBreakerConfig}};
+    use super::{Describe, Description};
+
+    struct GetUser {
+        info: MethodDefInfo,
+    }
+
+    impl MethodDef for GetUser {
+
+        const NAME: &'static str = "getUser";
+
+        type Request = Value;
+
+        type Response = Value;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    struct UserQuery {
+        name: String,
+    }
+
+    impl Message for UserQuery {
+        type MsgType = String;
+        type Encoder = JsonEncoder;
+        type Decoder = JsonDecoder<Self>;
+    }
+
+    struct FindUser {
+        info: MethodDefInfo,
+    }
+
+    impl MethodDef for FindUser {
+
+        const NAME: &'static str = "findUser";
+
+        type Request = UserQuery;
+
+        type Response = Value;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+    struct Users;
+
+    impl Invoker<u64> for Users {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, id: u64) -> Self::Res {
+            Ok(id.to_string())
+        }
+    }
+
+    impl Describe for Users {
+        fn describe(&self) -> Description {
+            Description::default().method::<GetUser>().method_with_schemas::<FindUser>()
+        }
+    }
+
+    struct Opaque;
+
+    impl Invoker<()> for Opaque {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: ()) -> Self::Res {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let mut manager = InvokerManager::new();
+        manager.add_invoker(CircuitBreaker::new(Users, CircuitBreakerConfig::default()));
+        manager.add_qualified("backup", Users);
+        manager.add_invoker(Opaque);
+        manager.describable::<CircuitBreaker<Users>>();
+        manager.describable::<Users>();
+
+        let report = serde_json::to_value(manager.report()).unwrap();
+        let invokers = report["invokers"].as_array().unwrap();
+        assert_eq!(3, invokers.len());
+
+        let opaque = invokers.iter().find(|i| i["type_name"].as_str().unwrap().ends_with("Opaque")).unwrap();
+        assert_eq!(Value::Null, opaque["description"]);
+
+        let breaker = invokers.iter().find(|i| i["type_name"].as_str().unwrap().contains("CircuitBreaker")).unwrap();
+        assert_eq!(json!(["circuit_breaker"]), breaker["description"]["layers"]);
+        assert_eq!("getUser", breaker["description"]["methods"][0]["name"]);
+        assert_eq!("serde_json::value::Value", breaker["description"]["methods"][0]["request_type"]);
+        assert_eq!(Value::Null, breaker["description"]["methods"][0]["request_schema"]);
+        let find = &breaker["description"]["methods"][1];
+        assert_eq!("string", find["request_schema"]["properties"]["name"]["type"]);
+        assert_eq!(json!(["name"]), find["request_schema"]["required"]);
+
+        let backup = invokers.iter().find(|i| i["qualifier"] != Value::Null).unwrap();
+        assert_eq!(json!({"Named": "backup"}), backup["qualifier"]);
+        assert_eq!(json!([]), backup["description"]["layers"]);
+    }
+
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+
+/// coarse classification of an [`InvokerError`], used by layers to decide what to do with a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    General,
+    Unavailable,
+    CircuitOpen,
+    ConcurrencyLimited,
+    RateLimited,
+    DeadlineExceeded,
+    NotRegistered,
+    MissingContext,
+    PermissionDenied,
+    NotFound,
+    InvalidPayload,
+}
+
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum InvokerError {
+    #[error("general error: {0}")]
+    GeneralError(Arc<anyhow::Error>),
+    #[error("no endpoint available")]
+    NoEndpoint,
+    #[error("circuit open for method `{0}`")]
+    CircuitOpen(&'static str),
+    #[error("concurrency limit reached for method `{0}`")]
+    ConcurrencyLimited(&'static str),
+    #[error("rate limit exceeded for key `{0}`")]
+    RateLimited(String),
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+    #[error("invoker `{0}` is not registered")]
+    NotRegistered(&'static str),
+    #[error("context entry `{0}` is missing")]
+    MissingContext(&'static str),
+    #[error("method `{method}` requires scope `{scope}`")]
+    PermissionDenied { method: &'static str, scope: String },
+    #[error("method `{service}/{method}` not found")]
+    NotFound { service: String, method: String },
+    #[error("invalid payload: {0}")]
+    InvalidPayload(crate::schema::Violations),
+}
+
+impl InvokerError {
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            InvokerError::GeneralError(_) => ErrorCode::General,
+            InvokerError::NoEndpoint => ErrorCode::Unavailable,
+            InvokerError::CircuitOpen(_) => ErrorCode::CircuitOpen,
+            InvokerError::ConcurrencyLimited(_) => ErrorCode::ConcurrencyLimited,
+            InvokerError::RateLimited(_) => ErrorCode::RateLimited,
+            InvokerError::DeadlineExceeded => ErrorCode::DeadlineExceeded,
+            InvokerError::NotRegistered(_) => ErrorCode::NotRegistered,
+            InvokerError::MissingContext(_) => ErrorCode::MissingContext,
+            InvokerError::PermissionDenied { .. } => ErrorCode::PermissionDenied,
+            InvokerError::NotFound { .. } => ErrorCode::NotFound,
+            InvokerError::InvalidPayload(_) => ErrorCode::InvalidPayload,
+        }
+    }
+
+}
+
+impl From<anyhow::Error> for InvokerError {
+
+    fn from(e: anyhow::Error) -> Self {
+        InvokerError::GeneralError(Arc::new(e))
+    }
+}
+
+
+/// response types which are able to carry an [`InvokerError`]
+///
+/// layers use this to fail a call without knowing the exact response type of the inner invoker
+pub trait FromInvokerError {
+
+    fn from_invoker_error(e: InvokerError) -> Self;
+
+}
+
+impl<T, E: From<InvokerError>> FromInvokerError for Result<T, E> {
+
+    fn from_invoker_error(e: InvokerError) -> Self {
+        Err(e.into())
+    }
+}
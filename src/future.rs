@@ -0,0 +1,70 @@
+use std::{future::Future, pin::Pin, task::{Context, Poll}};
+
+use futures_util::future::BoxFuture;
+use pin_project_lite::pin_project;
+
+use crate::error::{FromInvokerError, InvokerError};
+
+
+pin_project! {
+    /// response of invokers which answer asynchronously
+    pub struct InvokerFuture<Res> {
+        #[pin]
+        fut: BoxFuture<'static, Result<Res, InvokerError>>
+    }
+}
+
+impl<Res: Send + 'static> InvokerFuture<Res> {
+
+    /// create a new invoker future
+    pub fn new(fut: impl Future<Output = Result<Res, InvokerError>> + Send + 'static) -> Self {
+        Self {
+            fut: Box::pin(fut)
+        }
+    }
+
+    /// future which is ready with the given result
+    pub fn ready(res: Result<Res, InvokerError>) -> Self {
+        Self::new(futures_util::future::ready(res))
+    }
+
+}
+
+impl<Res> Future for InvokerFuture<Res> {
+
+    type Output = Result<Res, InvokerError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let proj = self.project();
+        proj.fut.poll(cx)
+    }
+}
+
+impl<Res: Send + 'static> FromInvokerError for InvokerFuture<Res> {
+
+    fn from_invoker_error(e: InvokerError) -> Self {
+        Self::ready(Err(e))
+    }
+}
+
+
+/// responses which become an [`InvokerFuture`], to treat sync and async invokers alike
+pub trait IntoInvokerFuture<Res> {
+
+    fn into_invoker_future(self) -> InvokerFuture<Res>;
+
+}
+
+impl<Res> IntoInvokerFuture<Res> for InvokerFuture<Res> {
+
+    fn into_invoker_future(self) -> InvokerFuture<Res> {
+        self
+    }
+}
+
+impl<Res: Send + 'static> IntoInvokerFuture<Res> for Result<Res, InvokerError> {
+
+    fn into_invoker_future(self) -> InvokerFuture<Res> {
+        InvokerFuture::ready(self)
+    }
+}
@@ -0,0 +1,337 @@
+//! handlers declaring the context entries they need as arguments
+//!
+//! a function like `fn(Ctx<Session>, Deadline, Req) -> Res` becomes an invoker with
+//! [`handler`]: every argument but the request is extracted from the
+//! [`InvokeContext`] through [`FromContext`], and a missing entry fails the call with
+//! [`InvokerError::MissingContext`] instead of panicking
+
+use std::{any::type_name, marker::PhantomData, ops::{Deref, DerefMut}, sync::{Arc, Mutex}};
+
+use crate::{context::{Deadline, Metadata, Method}, error::{FromInvokerError, InvokerError}, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// handler arguments taken from the context
+pub trait FromContext: Sized {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError>;
+
+    /// called once the handler returned, for arguments passing changes back to the context
+    fn write_back(_context: &mut InvokeContext) {}
+
+    /// called instead of the handler when another argument couldn't be taken from the
+    /// context, for arguments undoing what `from_context` did to it
+    fn give_back(self, _context: &mut InvokeContext) {}
+
+}
+
+fn cloned<T: Clone + 'static>(context: &InvokeContext) -> Result<T, InvokerError> {
+    context.get::<T>().cloned().ok_or(InvokerError::MissingContext(type_name::<T>()))
+}
+
+
+/// copy of the `T` entry of the context or its parents, see [`CtxMut`] for changing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ctx<T>(pub T);
+
+impl<T: Clone + 'static> FromContext for Ctx<T> {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        cloned(context).map(Ctx)
+    }
+}
+
+impl<T> Deref for Ctx<T> {
+
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+
+
+/// copy of the `T` entry of the context, put back into the context once the handler returned
+///
+/// so changes are seen by the layers the call returns through. the copy is put back when it
+/// is dropped, a handler keeping it past its return, e.g. in the future it answers with,
+/// leaves the entry as it was
+#[derive(Debug)]
+pub struct CtxMut<T: Send + 'static> {
+    value: Option<T>,
+    slot: Arc<Mutex<Option<T>>>,
+}
+
+/// where a [`CtxMut`] leaves its value for [`FromContext::write_back`]
+struct WriteBack<T>(Arc<Mutex<Option<T>>>);
+
+impl<T: Clone + Send + Sync + 'static> FromContext for CtxMut<T> {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        let value = cloned::<T>(context)?;
+        let slot = Arc::new(Mutex::new(None));
+        context.insert(WriteBack(slot.clone()));
+        Ok(CtxMut { value: Some(value), slot })
+    }
+
+    fn write_back(context: &mut InvokeContext) {
+        let value = context.take::<WriteBack<T>>().and_then(|w| w.0.lock().unwrap().take());
+        if let Some(value) = value {
+            context.insert_cloneable(value);
+        }
+    }
+
+    fn give_back(self, context: &mut InvokeContext) {
+        context.remove::<WriteBack<T>>();
+    }
+}
+
+impl<T: Send + 'static> Deref for CtxMut<T> {
+
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().expect("set until dropped")
+    }
+}
+
+impl<T: Send + 'static> DerefMut for CtxMut<T> {
+
+    fn deref_mut(&mut self) -> &mut T {
+        self.value.as_mut().expect("set until dropped")
+    }
+}
+
+impl<T: Send + 'static> Drop for CtxMut<T> {
+
+    fn drop(&mut self) {
+        *self.slot.lock().unwrap() = self.value.take();
+    }
+}
+
+
+/// the `T` entry moved out of the context itself, for entries which can't be cloned
+#[derive(Debug)]
+pub struct Take<T>(pub T);
+
+impl<T: Send + Sync + 'static> FromContext for Take<T> {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        context.take::<T>().map(Take).ok_or(InvokerError::MissingContext(type_name::<T>()))
+    }
+
+    fn give_back(self, context: &mut InvokeContext) {
+        context.insert(self.0);
+    }
+}
+
+
+impl FromContext for Metadata {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        cloned(context)
+    }
+}
+
+impl FromContext for Deadline {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        cloned(context)
+    }
+}
+
+impl FromContext for Method {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        cloned(context)
+    }
+}
+
+/// `None` instead of failing when the entry is missing
+impl<T: FromContext> FromContext for Option<T> {
+
+    fn from_context(context: &mut InvokeContext) -> Result<Self, InvokerError> {
+        match T::from_context(context) {
+            Ok(value) => Ok(Some(value)),
+            Err(InvokerError::MissingContext(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_back(context: &mut InvokeContext) {
+        T::write_back(context)
+    }
+}
+
+
+/// functions taking extractors `Args` and the request as the last argument
+pub trait Handler<Args, Req> {
+
+    type Res;
+
+    fn call(&self, context: &mut InvokeContext, req: Req) -> Self::Res;
+
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident),*) => {
+        impl<F, Req, Res, $($arg,)*> Handler<($($arg,)*), Req> for F
+        where
+            F: Fn($($arg,)* Req) -> Res,
+            Res: FromInvokerError,
+            $($arg: FromContext,)*
+        {
+
+            type Res = Res;
+
+            // every argument is taken before failing, so the ones which were can be given back
+            #[allow(non_snake_case, unused_variables, unused_mut, unreachable_patterns)]
+            fn call(&self, context: &mut InvokeContext, req: Req) -> Res {
+                $(let $arg = $arg::from_context(context);)*
+                match ($($arg,)*) {
+                    ($(Ok($arg),)*) => {
+                        let res = self($($arg,)* req);
+                        $($arg::write_back(context);)*
+                        res
+                    },
+                    ($($arg,)*) => {
+                        let mut err = None;
+                        $(
+                            match $arg {
+                                Ok(value) => value.give_back(context),
+                                Err(e) => { err.get_or_insert(e); },
+                            }
+                        )*
+                        Res::from_invoker_error(err.expect("one argument failed"))
+                    },
+                }
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(A1);
+impl_handler!(A1, A2);
+impl_handler!(A1, A2, A3);
+impl_handler!(A1, A2, A3, A4);
+impl_handler!(A1, A2, A3, A4, A5);
+
+
+/// invoker calling a [`Handler`], see [`handler`]
+pub struct HandlerInvoker<H, Args> {
+    handler: H,
+    _marker: PhantomData<fn() -> Args>,
+}
+
+/// turn `h` into an invoker, usable as a server handler or at the bottom of client layers
+pub fn handler<H, Args, Req>(h: H) -> HandlerInvoker<H, Args>
+where
+    H: Handler<Args, Req>
+{
+    HandlerInvoker { handler: h, _marker: PhantomData }
+}
+
+impl<H, Args, Req> Invoker<Req> for HandlerInvoker<H, Args>
+where
+    H: Handler<Args, Req>
+{
+
+    type Res = H::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        self.handler.call(context, req)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::time::Duration;
+
+    use futures_executor::block_on;
+
+    use crate::{context::{Deadline, Metadata}, error::{ErrorCode, InvokerError}, future::InvokerFuture, invoker_manager::{InvokeContext, Invoker}, Typed};
+    use super::{handler, Ctx, CtxMut, Take};
+
+    #[derive(Debug, Clone)]
+    struct Session {
+        user: String,
+    }
+
+    fn greet(session: Ctx<Session>, metadata: Option<Metadata>, req: String) -> Result<String, InvokerError> {
+        let lang = metadata.as_ref().and_then(|m| m.get("lang")).unwrap_or("en").to_owned();
+        Ok(format!("{} {} {}", lang, req, session.user))
+    }
+
+    #[test]
+    fn test_extract() {
+        let greet = handler(greet);
+        let mut context = InvokeContext::new();
+        context.with_context(Session { user: "alice".to_owned() });
+        assert_eq!("en hello alice", greet.invoke(&mut context, "hello".to_owned()).unwrap());
+
+        let mut metadata = Metadata::new();
+        metadata.insert("lang", "fr");
+        context.with_context(metadata);
+        assert_eq!("fr hello alice", greet.invoke(&mut context, "hello".to_owned()).unwrap());
+
+        let err = greet.invoke(&mut InvokeContext::new(), "hello".to_owned()).unwrap_err();
+        assert_eq!(ErrorCode::MissingContext, err.code());
+        assert!(err.to_string().contains("Session"));
+    }
+
+    #[test]
+    fn test_async_handler() {
+        let counter = handler(|Take(times): Take<Vec<u32>>, deadline: Deadline, req: u32| {
+            InvokerFuture::new(async move {
+                assert!(!deadline.is_expired());
+                Ok(times.len() as u32 + req)
+            })
+        });
+        let mut context = InvokeContext::new();
+        context.with_context(vec![1u32, 2]);
+        context.with_context(Deadline::after(Duration::from_secs(1)));
+        assert_eq!(12, block_on(counter.invoke(&mut context, 10)).unwrap());
+
+        // the entry was moved out by the first call
+        let err = block_on(counter.invoke(&mut context, 10)).unwrap_err();
+        assert!(matches!(err, InvokerError::MissingContext(_)));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let login = handler(|mut session: CtxMut<Session>, req: String| -> Result<(), InvokerError> {
+            session.user = req;
+            Ok(())
+        });
+        let mut context = InvokeContext::new();
+        context.with_context(Session { user: "alice".to_owned() });
+        login.invoke(&mut context, "bob".to_owned()).unwrap();
+        assert_eq!("bob", context.get::<Session>().unwrap().user);
+
+        // the entry of a parent is copied into the context
+        let mut child = context.fork();
+        login.invoke(&mut child, "carol".to_owned()).unwrap();
+        assert_eq!("carol", child.get::<Session>().unwrap().user);
+        assert_eq!("bob", context.get::<Session>().unwrap().user);
+    }
+
+    #[test]
+    fn test_missing_argument() {
+        let login = handler(|_: Take<String>, _: CtxMut<Session>, _: Ctx<Deadline>, _: ()| -> Result<(), InvokerError> {
+            unreachable!()
+        });
+        let mut context = InvokeContext::new();
+        context.with_context("token".to_owned());
+        context.with_context(Session { user: "alice".to_owned() });
+        let err = login.invoke(&mut context, ()).unwrap_err();
+        assert!(matches!(err, InvokerError::MissingContext(_)));
+        // the arguments taken before are given back
+        assert_eq!("token", context.get::<String>().unwrap());
+        assert_eq!("alice", context.get::<Session>().unwrap().user);
+        assert_eq!(2, context.type_names().count());
+    }
+
+}
@@ -0,0 +1,253 @@
+//! invokers built by factories which pull their dependencies from the manager
+//!
+//! [`InvokerManager::add_factory`] registers how to build a value, invokers and shared
+//! resources alike, and it is built on its first [`InvokerManager::resolve`]. a factory gets
+//! a [`Resolver`] for the values it depends on, so e.g. an aggregator is handed both
+//! backends it calls. every value is built once and shared as `Arc`. invokers registered
+//! as `Arc<T>` through [`InvokerManager::add_invoker`] or [`InvokerManager::add_qualified`]
+//! are resolved as well
+
+use std::{any::{type_name, Any, TypeId}, fmt, sync::{Arc, Mutex}};
+
+use once_cell::sync::OnceCell;
+
+use crate::{invoker_manager::{InvokerManager, Qualifier}, Typed};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    #[error("`{0}` has no factory")]
+    NotRegistered(&'static str),
+    #[error("dependency cycle {}", .0.join(" -> "))]
+    Cycle(Vec<&'static str>),
+    #[error("factory of `{name}` failed")]
+    Failed { name: &'static str, #[source] source: anyhow::Error },
+}
+
+
+pub(crate) type BuildLock = Mutex<()>;
+
+pub(crate) type LazyEntry = (&'static str, Box<dyn Any + Send + Sync>);
+
+type FactoryFn<T> = Box<dyn Fn(&Resolver<'_>) -> anyhow::Result<T> + Send + Sync>;
+
+pub(crate) struct Lazy<T> {
+    factory: Option<FactoryFn<T>>,
+    value: OnceCell<Arc<T>>,
+}
+
+impl<T> fmt::Debug for Lazy<T> {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lazy").field("built", &self.value.get().is_some()).finish()
+    }
+}
+
+
+/// handed to factories to resolve what they depend on
+pub struct Resolver<'a> {
+    manager: &'a InvokerManager,
+    // types being built, outermost first
+    path: Vec<(TypeId, &'static str)>,
+}
+
+impl<'a> Resolver<'a> {
+
+    /// the `T` of a factory or [`InvokerManager::add_shared`], else an `Arc<T>` registered
+    /// with [`InvokerManager::add_invoker`]
+    pub fn get<T: 'static + Send + Sync>(&self) -> Result<Arc<T>, ResolveError> {
+        let lazy = match self.manager.lazy.get(&TypeId::of::<T>()).and_then(|(_, a)| a.downcast_ref::<Lazy<T>>()) {
+            Some(lazy) => lazy,
+            None => {
+                return self.manager.get::<Arc<T>>().cloned().ok_or(ResolveError::NotRegistered(type_name::<T>()));
+            },
+        };
+        if let Some(value) = lazy.value.get() {
+            return Ok(value.clone());
+        }
+        if self.path.iter().any(|(id, _)| *id == TypeId::of::<T>()) {
+            let mut cycle = self.path.iter().map(|(_, name)| *name).skip_while(|name| *name != type_name::<T>()).collect::<Vec<_>>();
+            cycle.push(type_name::<T>());
+            return Err(ResolveError::Cycle(cycle));
+        }
+
+        let factory = lazy.factory.as_ref().ok_or(ResolveError::NotRegistered(type_name::<T>()))?;
+        let mut path = self.path.clone();
+        path.push((TypeId::of::<T>(), type_name::<T>()));
+        let resolver = Resolver { manager: self.manager, path };
+        let value = lazy.value.get_or_try_init(|| factory(&resolver).map(Arc::new)).map_err(|e| {
+            // errors of dependencies are passed on as they are
+            match e.downcast::<ResolveError>() {
+                Ok(e) => e,
+                Err(source) => ResolveError::Failed { name: type_name::<T>(), source },
+            }
+        })?;
+        Ok(value.clone())
+    }
+
+    /// the `Arc<T>` registered with [`InvokerManager::add_qualified`] under `qualifier`,
+    /// else what [`Resolver::get`] resolves
+    pub fn get_qualified<T: 'static + Send + Sync>(&self, qualifier: impl Into<Qualifier>) -> Result<Arc<T>, ResolveError> {
+        match self.manager.get_qualified::<Arc<T>>(&qualifier.into()) {
+            Some(value) => Ok(value.clone()),
+            None => self.get(),
+        }
+    }
+
+}
+
+
+impl InvokerManager {
+
+    /// build `T` with `factory` when it's resolved the first time
+    pub fn add_factory<T, F>(&mut self, factory: F)
+    where
+        T: 'static + Send + Sync,
+        F: Fn(&Resolver<'_>) -> anyhow::Result<T> + Send + Sync + 'static
+    {
+        self.add_lazy(Lazy { factory: Some(Box::new(factory)), value: OnceCell::new() });
+    }
+
+    /// a value shared with the factories, like a connection pool
+    pub fn add_shared<T: 'static + Send + Sync>(&mut self, value: Arc<T>) {
+        self.add_lazy(Lazy { factory: None, value: OnceCell::with_value(value) });
+    }
+
+    /// the `T` of [`InvokerManager::add_factory`] or [`InvokerManager::add_shared`], built
+    /// along with its dependencies if it isn't yet, else a registered `Arc<T>`
+    pub fn resolve<T: 'static + Send + Sync>(&self) -> Result<Arc<T>, ResolveError> {
+        if let Some(value) = self.resolved::<T>() {
+            return Ok(value);
+        }
+        // one build at a time, two threads building each other's dependencies would
+        // otherwise wait for each other forever
+        let _build = self.build_lock.lock().unwrap();
+        Resolver { manager: self, path: Vec::new() }.get()
+    }
+
+    fn resolved<T: 'static>(&self) -> Option<Arc<T>> {
+        self.lazy.get(&TypeId::of::<T>())
+            .and_then(|(_, a)| a.downcast_ref::<Lazy<T>>())
+            .and_then(|lazy| lazy.value.get().cloned())
+    }
+
+    fn add_lazy<T: 'static + Send + Sync>(&mut self, lazy: Lazy<T>) {
+        self.lazy.insert(TypeId::of::<T>(), (type_name::<T>(), Box::new(lazy)));
+    }
+
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use crate::{error::InvokerError, invoker_manager::{InvokeContext, Invoker, InvokerManager}, Typed};
+    use super::ResolveError;
+
+    struct Users;
+
+    struct Orders;
+
+    impl Invoker<u32> for Users {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, id: u32) -> Self::Res {
+            Ok(format!("user {}", id))
+        }
+    }
+
+    impl Invoker<u32> for Orders {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, id: u32) -> Self::Res {
+            Ok(format!("orders of {}", id))
+        }
+    }
+
+    struct Prefix(&'static str);
+
+    /// calls both backends
+    struct Profile {
+        users: Arc<Users>,
+        orders: Arc<Orders>,
+        prefix: Arc<Prefix>,
+    }
+
+    impl Invoker<u32> for Profile {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, context: &mut InvokeContext, id: u32) -> Self::Res {
+            let user = self.users.invoke(context, id)?;
+            let orders = self.orders.invoke(context, id)?;
+            Ok(format!("{}{}, {}", self.prefix.0, user, orders))
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let mut manager = InvokerManager::new();
+        let counter = builds.clone();
+        manager.add_factory(move |resolver| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Profile { users: resolver.get()?, orders: resolver.get()?, prefix: resolver.get()? })
+        });
+        manager.add_factory(|_| Ok(Users));
+        manager.add_factory(|_| Ok(Orders));
+        manager.add_shared(Arc::new(Prefix("> ")));
+
+        let profile = manager.resolve::<Profile>().unwrap();
+        assert_eq!("> user 1, orders of 1", profile.invoke(&mut InvokeContext::new(), 1).unwrap());
+        // built once and shared
+        let again = manager.resolve::<Profile>().unwrap();
+        assert!(Arc::ptr_eq(&profile, &again));
+        assert!(Arc::ptr_eq(&profile.users, &manager.resolve::<Users>().unwrap()));
+        assert_eq!(1, builds.load(Ordering::SeqCst));
+
+        assert!(matches!(manager.resolve::<String>(), Err(ResolveError::NotRegistered(_))));
+    }
+
+    #[test]
+    fn test_resolve_registered() {
+        let mut manager = InvokerManager::new();
+        manager.add_invoker(Arc::new(Users));
+        manager.add_qualified("archive", Arc::new(Orders));
+        manager.add_shared(Arc::new(Prefix("")));
+        manager.add_factory(|resolver| {
+            Ok(Profile { users: resolver.get()?, orders: resolver.get_qualified("archive")?, prefix: resolver.get()? })
+        });
+
+        let profile = manager.resolve::<Profile>().unwrap();
+        assert_eq!("user 1, orders of 1", profile.invoke(&mut InvokeContext::new(), 1).unwrap());
+        // the registered instance itself
+        assert!(Arc::ptr_eq(&profile.users, manager.get::<Arc<Users>>().unwrap()));
+        assert!(matches!(manager.resolve::<Orders>(), Err(ResolveError::NotRegistered(_))));
+    }
+
+    struct A(#[allow(dead_code)] Arc<B>);
+
+    struct B(#[allow(dead_code)] Arc<A>);
+
+    #[test]
+    fn test_cycle() {
+        let mut manager = InvokerManager::new();
+        manager.add_factory(|resolver| Ok(A(resolver.get()?)));
+        manager.add_factory(|resolver| Ok(B(resolver.get()?)));
+        manager.add_factory::<Orders, _>(|_| anyhow::bail!("no connection"));
+
+        match manager.resolve::<A>() {
+            Err(ResolveError::Cycle(cycle)) => assert_eq!(3, cycle.len()),
+            res => panic!("unexpected {:?}", res.err()),
+        }
+        match manager.resolve::<Orders>() {
+            Err(ResolveError::Failed { source, .. }) => assert_eq!("no connection", source.to_string()),
+            res => panic!("unexpected {:?}", res.err()),
+        }
+    }
+
+}
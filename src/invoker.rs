@@ -1,3 +1,6 @@
+// exploration modules, kept for reference
+#![allow(dead_code)]
+
 
 mod inovker1 {
     use std::fmt::Debug;
@@ -175,7 +178,7 @@ mod invoker2 {
 
 
 mod inovker3 {
-    use std::{any::Any, fmt::Debug, future::Future};
+    use std::{fmt::Debug, future::Future};
 
     use futures_util::future::BoxFuture;
     use pin_project_lite::pin_project;
@@ -342,11 +345,12 @@ mod invoker4 {
 }
 
 
-mod invoker5 {
+pub mod invoker5 {
 
-    use std::{collections::HashMap, any::{TypeId, Any}, future::Future, marker::PhantomData};
+    use std::{collections::BTreeMap, future::Future, marker::PhantomData, time::Duration};
 
     use futures_util::future::BoxFuture;
+    use once_cell::sync::Lazy;
     use pin_project_lite::pin_project;
     use serde_json::Value;
 
@@ -382,16 +386,125 @@ mod invoker5 {
     }
 
 
-    #[derive(Debug, Clone)]
-    pub struct MethodDefInfo{
-        map: HashMap<String, String>,
+    /// options of a method, the layers wrapping its invoker act on them
+    #[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+    pub struct MethodDefInfo {
+        /// how long callers wait for an answer
+        #[serde(serialize_with = "millis::option")]
+        pub timeout: Option<Duration>,
+        /// the method may be invoked more than once for the same request
+        pub idempotent: bool,
+        /// responses of the method may be cached
+        pub cacheable: bool,
+        pub retry: Option<RetryPolicy>,
+        /// callers don't wait for a response
+        pub one_way: bool,
+        pub compression: Option<Compression>,
+        /// scope callers must be granted to invoke the method
+        pub auth_scope: Option<String>,
+        pub deprecation: Option<Deprecation>,
+        /// options without a field of their own
+        pub extensions: BTreeMap<String, String>,
     }
 
     impl MethodDefInfo {
 
         /// create new method def info
         pub fn new() -> Self {
-            Self { map: HashMap::new() }
+            Self::default()
+        }
+
+        /// set the extension `key`
+        pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+            self.extensions.insert(key.into(), value.into())
+        }
+
+        /// the extension `key`
+        pub fn get(&self, key: &str) -> Option<&str> {
+            self.extensions.get(key).map(|v| v.as_str())
+        }
+
+        /// whether the method may be invoked more than once for the same request
+        pub fn is_idempotent(&self) -> bool {
+            self.idempotent
+        }
+
+        /// whether responses of the method may be cached
+        pub fn is_cacheable(&self) -> bool {
+            self.cacheable
+        }
+
+    }
+
+
+    /// how often and how fast failed calls are retried, see [`Retry`](crate::layer::retry::Retry)
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct RetryPolicy {
+        /// attempts per call, including the first one
+        pub max_attempts: usize,
+        /// wait before the first retry, doubled for every further one
+        #[serde(rename = "backoff_ms", serialize_with = "millis::duration", deserialize_with = "millis::to_duration")]
+        pub backoff: Duration,
+        #[serde(rename = "max_backoff_ms", serialize_with = "millis::duration", deserialize_with = "millis::to_duration")]
+        pub max_backoff: Duration,
+    }
+
+    impl Default for RetryPolicy {
+
+        fn default() -> Self {
+            Self { max_attempts: 3, backoff: Duration::from_millis(50), max_backoff: Duration::from_secs(1) }
+        }
+    }
+
+    impl RetryPolicy {
+
+        /// wait before the given retry, the first one is `1`
+        pub fn backoff(&self, retry: usize) -> Duration {
+            let factor = 1u32.checked_shl(retry.saturating_sub(1) as u32).unwrap_or(u32::MAX);
+            self.backoff.saturating_mul(factor).min(self.max_backoff)
+        }
+
+    }
+
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Compression {
+        Gzip,
+        Deflate,
+        Zstd,
+    }
+
+
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+    pub struct Deprecation {
+        /// what callers should know, like the method replacing this one
+        pub note: String,
+        pub since: Option<String>,
+    }
+
+
+    // durations as millis, the way they're configured
+    mod millis {
+
+        use std::time::Duration;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn duration<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_u64(d.as_millis() as u64)
+        }
+
+        pub fn to_duration<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+            u64::deserialize(d).map(Duration::from_millis)
+        }
+
+        pub fn option<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+            match d {
+                Some(d) => s.serialize_some(&(d.as_millis() as u64)),
+                None => s.serialize_none(),
+            }
         }
 
     }
@@ -432,8 +545,9 @@ mod invoker5 {
 
 
 
-    /// impl
-   
+    // impl
+
+    #[derive(Debug, Clone, Copy, Default)]
     pub struct JsonEncoder;
     pub struct JsonDecoder<V> {
         _m: PhantomData<V>
@@ -473,6 +587,9 @@ mod invoker5 {
         type Decoder = JsonDecoder<Self>; 
     }
 
+    /// info of [`GenericMethod`], nothing is known about the methods it stands for
+    static GENERIC_METHOD_INFO: Lazy<MethodDefInfo> = Lazy::new(MethodDefInfo::default);
+
     pub struct GenericMethod;
     impl MethodDef for GenericMethod {
 
@@ -483,7 +600,7 @@ mod invoker5 {
         type Response = Value;
 
         fn get_method_def_info(&self) -> &MethodDefInfo {
-            todo!()
+            &GENERIC_METHOD_INFO
         }
     }
 
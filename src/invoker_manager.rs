@@ -1,11 +1,224 @@
 
-use std::{any::{Any, TypeId}, collections::HashMap};
+use std::{any::{type_name, Any, TypeId}, collections::HashMap, fmt, hash::{BuildHasherDefault, Hasher}, marker::PhantomData, mem, sync::{Arc, Mutex, Weak}, time::{Duration, Instant}};
 
-use crate::Typed;
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use smallvec::SmallVec;
+
+use crate::{describe, error::{FromInvokerError, InvokerError}, inject, lifecycle, provide::{self, Demand, Provide}, Typed};
 
 
 type BoxAny = Box<dyn Any + Send + Sync>;
 
+
+/// entries kept inline before a [`TypeMap`] moves them to a hash map
+const INLINE_ENTRIES: usize = 8;
+
+/// copies an entry, recorded for entries which are `Clone` so children can copy on write
+type Cloner = fn(&BoxAny) -> BoxAny;
+
+fn cloner<T: Clone + 'static + Send + Sync>(value: &BoxAny) -> BoxAny {
+    Box::new(value.downcast_ref::<T>().expect("cloner is keyed by its type").clone())
+}
+
+type Slot = (TypeId, &'static str, BoxAny, Option<Cloner>);
+
+/// hasher for keys which already are hashes, like `TypeId`
+#[derive(Default)]
+struct IdHasher(u64);
+
+impl Hasher for IdHasher {
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*b);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
+}
+
+// being inline is the point, the context is moved rarely
+#[allow(clippy::large_enum_variant)]
+enum Storage {
+    Inline(SmallVec<[Slot; INLINE_ENTRIES]>),
+    Spilled(HashMap<TypeId, (&'static str, BoxAny, Option<Cloner>), BuildHasherDefault<IdHasher>>),
+}
+
+/// values keyed by their type, the storage of [`InvokeContext`] and [`InvokerManager`]
+///
+/// most contexts hold a few entries, those are kept inline and found by a linear scan, so
+/// an empty map allocates nothing
+struct TypeMap {
+    storage: Storage,
+}
+
+impl Default for TypeMap {
+
+    fn default() -> Self {
+        Self { storage: Storage::Inline(SmallVec::new()) }
+    }
+}
+
+impl TypeMap {
+
+    fn insert<T: 'static + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.insert_with::<T>(Box::new(value), None)
+    }
+
+    fn insert_cloneable<T: Clone + 'static + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.insert_with::<T>(Box::new(value), Some(cloner::<T>))
+    }
+
+    fn insert_with<T: 'static>(&mut self, value: BoxAny, cloner: Option<Cloner>) -> Option<T> {
+        let old = self.insert_boxed(TypeId::of::<T>(), type_name::<T>(), value, cloner);
+        old.and_then(|a| a.downcast().ok()).map(|a| *a)
+    }
+
+    fn insert_boxed(&mut self, id: TypeId, name: &'static str, value: BoxAny, cloner: Option<Cloner>) -> Option<BoxAny> {
+        match &mut self.storage {
+            Storage::Inline(slots) => {
+                if let Some(slot) = slots.iter_mut().find(|s| s.0 == id) {
+                    slot.3 = cloner;
+                    return Some(mem::replace(&mut slot.2, value));
+                }
+                if slots.len() < INLINE_ENTRIES {
+                    slots.push((id, name, value, cloner));
+                    return None;
+                }
+                let mut map: HashMap<_, _, BuildHasherDefault<IdHasher>> = slots.drain(..).map(|(id, name, a, c)| (id, (name, a, c))).collect();
+                map.insert(id, (name, value, cloner));
+                self.storage = Storage::Spilled(map);
+                None
+            },
+            Storage::Spilled(map) => map.insert(id, (name, value, cloner)).map(|(_, a, _)| a),
+        }
+    }
+
+    fn get_slot(&self, id: TypeId) -> Option<(&'static str, &BoxAny, Option<Cloner>)> {
+        match &self.storage {
+            Storage::Inline(slots) => slots.iter().find(|s| s.0 == id).map(|s| (s.1, &s.2, s.3)),
+            Storage::Spilled(map) => map.get(&id).map(|(name, a, c)| (*name, a, *c)),
+        }
+    }
+
+    fn get_boxed(&self, id: TypeId) -> Option<&BoxAny> {
+        self.get_slot(id).map(|(_, a, _)| a)
+    }
+
+    fn get_boxed_mut(&mut self, id: TypeId) -> Option<&mut BoxAny> {
+        match &mut self.storage {
+            Storage::Inline(slots) => slots.iter_mut().find(|s| s.0 == id).map(|s| &mut s.2),
+            Storage::Spilled(map) => map.get_mut(&id).map(|(_, a, _)| a),
+        }
+    }
+
+    fn get<T: 'static>(&self) -> Option<&T> {
+        self.get_boxed(TypeId::of::<T>()).and_then(|a| a.downcast_ref())
+    }
+
+    fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.get_boxed_mut(TypeId::of::<T>()).and_then(|a| a.downcast_mut())
+    }
+
+    fn take<T: 'static>(&mut self) -> Option<T> {
+        let id = TypeId::of::<T>();
+        let old = match &mut self.storage {
+            Storage::Inline(slots) => slots.iter().position(|s| s.0 == id).map(|i| slots.swap_remove(i).2),
+            Storage::Spilled(map) => map.remove(&id).map(|(_, a, _)| a),
+        };
+        old.and_then(|a| a.downcast().ok()).map(|a| *a)
+    }
+
+    fn contains<T: 'static>(&self) -> bool {
+        self.get_boxed(TypeId::of::<T>()).is_some()
+    }
+
+    /// copy of the entries which have a cloner, the others are left out
+    fn cloned(&self) -> TypeMap {
+        let mut copy = TypeMap::default();
+        let slots: Box<dyn Iterator<Item = (TypeId, &'static str, &BoxAny, Option<Cloner>)>> = match &self.storage {
+            Storage::Inline(slots) => Box::new(slots.iter().map(|(id, name, a, c)| (*id, *name, a, *c))),
+            Storage::Spilled(map) => Box::new(map.iter().map(|(id, (name, a, c))| (*id, *name, a, *c))),
+        };
+        for (id, name, value, cloner) in slots {
+            if let Some(cloner) = cloner {
+                copy.insert_boxed(id, name, cloner(value), Some(cloner));
+            }
+        }
+        copy
+    }
+
+    fn entry<T: 'static + Send + Sync>(&mut self, cloner: Option<Cloner>) -> Entry<'_, T> {
+        Entry { map: self, cloner, _marker: PhantomData }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TypeId, &'static str, &BoxAny)> + '_> {
+        match &self.storage {
+            Storage::Inline(slots) => Box::new(slots.iter().map(|(id, name, a, _)| (*id, *name, a))),
+            Storage::Spilled(map) => Box::new(map.iter().map(|(id, (name, a, _))| (*id, *name, a))),
+        }
+    }
+
+    fn type_names(&self) -> Box<dyn Iterator<Item = &'static str> + '_> {
+        match &self.storage {
+            Storage::Inline(slots) => Box::new(slots.iter().map(|s| s.1)),
+            Storage::Spilled(map) => Box::new(map.values().map(|(name, _, _)| *name)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match &self.storage {
+            Storage::Inline(slots) => slots.is_empty(),
+            Storage::Spilled(map) => map.is_empty(),
+        }
+    }
+
+}
+
+impl fmt::Debug for TypeMap {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.type_names()).finish()
+    }
+}
+
+
+/// entry of type `T` in an [`InvokeContext`] or [`InvokerManager`]
+pub struct Entry<'a, T> {
+    map: &'a mut TypeMap,
+    cloner: Option<Cloner>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: 'static + Send + Sync> Entry<'a, T> {
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> T) -> &'a mut T {
+        if !self.map.contains::<T>() {
+            self.map.insert_with::<T>(Box::new(f()), self.cloner);
+        }
+        self.map.get_mut().expect("entry is keyed by its type")
+    }
+
+    pub fn or_insert(self, value: T) -> &'a mut T {
+        self.or_insert_with(|| value)
+    }
+
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default
+    {
+        self.or_insert_with(T::default)
+    }
+
+}
+
 pub trait Invoker<Req> {
 
     type Res;
@@ -14,58 +227,370 @@ pub trait Invoker<Req> {
     
 }
 
+impl<Req, I: Invoker<Req>> Invoker<Req> for Arc<I> {
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        (**self).invoke(context, req)
+    }
+}
+
 
 
+/// typed entries of one invocation
+///
+/// a context may have a parent, entries missing in the context are looked up in the
+/// parent chain. a child never changes its parent, it shadows parent entries with its own.
+/// [`InvokeContext::fork`] and [`InvokeContext::join`] build on this for concurrent calls
+/// made on behalf of one request
 #[derive(Debug)]
 pub struct InvokeContext {
-    data: HashMap<TypeId, BoxAny>
+    data: TypeMap,
+    parent: Option<Arc<InvokeContext>>,
+}
+
+impl Default for InvokeContext {
+
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InvokeContext {
 
     pub fn new() -> Self {
-        Self { data: HashMap::new() }
+        Self { data: TypeMap::default(), parent: None }
+    }
+
+    /// child context seeing every entry of `parent`, e.g. for a downstream call inheriting
+    /// the request scoped context
+    pub fn with_parent(parent: Arc<InvokeContext>) -> Self {
+        Self { data: TypeMap::default(), parent: Some(parent) }
+    }
+
+    pub fn parent(&self) -> Option<&Arc<InvokeContext>> {
+        self.parent.as_ref()
     }
 
     pub fn with_context<I: 'static + Send + Sync>(&mut self, context: I) {
-        let id = TypeId::of::<I>();
-        self.data.insert(id, Box::new(context));
+        self.data.insert(context);
+    }
+
+    /// like [`InvokeContext::with_context`], returning the entry this context had before
+    pub fn insert<I: 'static + Send + Sync>(&mut self, context: I) -> Option<I> {
+        self.data.insert(context)
+    }
+
+    /// like [`InvokeContext::insert`] for entries which children and forks can copy on
+    /// write, see [`Typed::get_mut`]
+    pub fn insert_cloneable<I: Clone + 'static + Send + Sync>(&mut self, context: I) -> Option<I> {
+        self.data.insert_cloneable(context)
+    }
+
+    /// remove the entry of this context, returns whether there was one. an entry of the
+    /// parents shows through again afterwards
+    pub fn remove<I: 'static>(&mut self) -> bool {
+        self.data.take::<I>().is_some()
+    }
+
+    pub fn take<I: 'static>(&mut self) -> Option<I> {
+        self.data.take()
+    }
+
+    /// whether this context or one of its parents has an `I`
+    pub fn contains<I: 'static>(&self) -> bool {
+        self.data.contains::<I>() || self.parent.as_ref().map(|p| p.contains::<I>()).unwrap_or(false)
+    }
+
+    /// entry of this context itself, parents are not looked at
+    pub fn entry<I: 'static + Send + Sync>(&mut self) -> Entry<'_, I> {
+        self.data.entry(None)
+    }
+
+    /// type names of the entries of this context itself, for debugging
+    pub fn type_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.data.type_names()
+    }
+
+    /// add a member handing out references through [`Provide`], later ones are asked first
+    pub fn with_provider<P: Provide + 'static + Send + Sync>(&mut self, provider: P) {
+        self.data.entry::<Providers>(None).or_default().0.push(Box::new(provider));
+    }
+
+    /// reference of type `T` from the providers of this context or its parents, the
+    /// one to ask for trait objects like `dyn Authenticator`
+    pub fn request_ref<T: ?Sized + 'static>(&self) -> Option<&T> {
+        provide::request_ref(self)
+    }
+
+    // the slot of an entry of the parents, the nearest one first
+    fn inherited_slot(&self, id: TypeId) -> Option<(&'static str, &BoxAny, Option<Cloner>)> {
+        let parent = self.parent.as_ref()?;
+        parent.data.get_slot(id).or_else(|| parent.inherited_slot(id))
     }
 
+    /// freeze the entries of this context into a shared snapshot
+    pub fn freeze(self) -> Arc<InvokeContext> {
+        match self.parent {
+            // nothing of its own, the parent already is the snapshot
+            Some(parent) if self.data.is_empty() => parent,
+            _ => Arc::new(self),
+        }
+    }
+
+    /// copy of this context sharing its parents, with the entries inserted through
+    /// [`InvokeContext::insert_cloneable`]. unlike [`InvokeContext::fork`] this context is
+    /// left as it is
+    pub fn snapshot(&self) -> InvokeContext {
+        Self { data: self.data.cloned(), parent: self.parent.clone() }
+    }
+
+    /// child context for one of many concurrent calls
+    ///
+    /// the entries of this context move into a snapshot shared by this context and every
+    /// child, so forking copies no entry. both sides keep their own overlay from then on,
+    /// an entry is copied into it when it's first changed through `get_mut`. use
+    /// [`InvokeContext::join`] to take results of a child back
+    pub fn fork(&mut self) -> InvokeContext {
+        let snapshot = std::mem::take(self).freeze();
+        self.parent = Some(snapshot.clone());
+        InvokeContext::with_parent(snapshot)
+    }
+
+    /// merge the `I` entry a forked child has of its own into this context,
+    /// returns whether the child had one
+    pub fn join<I: Merge + Clone + 'static + Send + Sync>(&mut self, child: &mut InvokeContext) -> bool {
+        let value = match child.data.take::<I>() {
+            Some(value) => value,
+            None => return false,
+        };
+        let snapshot = child.parent.clone();
+        let base = snapshot.as_ref().and_then(|s| s.get::<I>());
+        match self.get_mut::<I>() {
+            Some(current) => current.merge(base, value),
+            None => {
+                self.insert_cloneable(value);
+            }
+        }
+        true
+    }
+
+}
+
+
+#[derive(Default)]
+struct Providers(Vec<Box<dyn Provide + Send + Sync>>);
+
+impl Provide for InvokeContext {
+
+    fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
+        if let Some(providers) = self.data.get::<Providers>() {
+            for provider in providers.0.iter().rev() {
+                provider.provide(demand);
+            }
+        }
+        if let Some(parent) = &self.parent {
+            parent.provide(demand);
+        }
+    }
+}
+
+
+/// entries which results of forked calls are merged into, see [`InvokeContext::join`]
+pub trait Merge {
+
+    /// `base` is the value the child was forked with, if there was one
+    fn merge(&mut self, base: Option<&Self>, other: Self);
+
 }
 
 
 impl Typed for InvokeContext {
 
     fn get<I: 'static>(&self) -> Option<&I> {
-        let id = TypeId::of::<I>();
-        self.data.get(&id).map(|a| a.downcast_ref()).flatten()
+        match self.data.get() {
+            Some(value) => Some(value),
+            None => self.parent.as_ref().and_then(|p| p.get()),
+        }
     }
 
+    /// an entry of the parents is copied into this context first, the parents keep their
+    /// value. `None` for an inherited entry which wasn't inserted through
+    /// [`InvokeContext::insert_cloneable`], it can't be copied
     fn get_mut<I: 'static>(&mut self) -> Option<&mut I> {
         let id = TypeId::of::<I>();
-        self.data.get_mut(&id).map(|a| a.downcast_mut()).flatten()
+        if !self.data.contains::<I>() {
+            let (name, value, cloner) = self.inherited_slot(id)?;
+            let copy = cloner?(value);
+            self.data.insert_boxed(id, name, copy, cloner);
+        }
+        self.data.get_mut()
+    }
+}
+
+
+/// tells registrations of the same type apart
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Qualifier {
+    /// e.g. `primary` and `backup` instances of one invoker type
+    Named(String),
+    /// like dubbo's group and version of a service
+    Service(ServiceKey),
+}
+
+impl From<&str> for Qualifier {
+
+    fn from(name: &str) -> Self {
+        Qualifier::Named(name.to_owned())
+    }
+}
+
+impl From<ServiceKey> for Qualifier {
+
+    fn from(key: ServiceKey) -> Self {
+        Qualifier::Service(key)
     }
 }
 
 
+/// group and version of a service, `None` being the default one
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct ServiceKey {
+    pub group: Option<String>,
+    pub version: Option<String>,
+}
+
+impl ServiceKey {
+
+    pub fn new(group: impl Into<String>, version: impl Into<String>) -> Self {
+        Self { group: Some(group.into()), version: Some(version.into()) }
+    }
+
+    pub fn group(group: impl Into<String>) -> Self {
+        Self { group: Some(group.into()), version: None }
+    }
+
+    /// the key itself, then without version, then the default service
+    fn fallbacks(&self) -> impl Iterator<Item = ServiceKey> + '_ {
+        let without_version = Self { group: self.group.clone(), version: None };
+        [self.clone(), without_version, Self::default()].into_iter()
+    }
+
+}
+
+
 #[derive(Debug)]
 pub struct InvokerManager {
-    invokers: HashMap<TypeId, BoxAny>
+    invokers: TypeMap,
+    qualified: HashMap<(TypeId, Qualifier), (&'static str, BoxAny)>,
+    pub(crate) managed: Vec<lifecycle::Managed>,
+    pub(crate) lazy: HashMap<TypeId, inject::LazyEntry>,
+    pub(crate) build_lock: inject::BuildLock,
+    pub(crate) describers: HashMap<TypeId, describe::Describer>,
+}
+
+impl Default for InvokerManager {
+
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InvokerManager {
 
     pub fn new() -> Self {
-        Self { invokers: HashMap::new() }
+        Self {
+            invokers: TypeMap::default(),
+            qualified: HashMap::new(),
+            managed: Vec::new(),
+            lazy: HashMap::new(),
+            build_lock: Default::default(),
+            describers: HashMap::new(),
+        }
+    }
+
+    /// register `invoker` next to other instances of its type, returns the one registered
+    /// before under the same qualifier
+    pub fn add_qualified<Req, I>(&mut self, qualifier: impl Into<Qualifier>, invoker: I) -> Option<I>
+    where
+        I: Invoker<Req> + 'static + Send + Sync
+    {
+        self.insert_qualified(qualifier.into(), invoker)
     }
 
-    pub fn add_invoker<Req, I>(&mut self, invoker: I)
+    /// the `I` registered under `qualifier`, the unqualified one if there is none
+    pub fn get_qualified<I: 'static>(&self, qualifier: &Qualifier) -> Option<&I> {
+        self.qualified.get(&(TypeId::of::<I>(), qualifier.clone()))
+            .and_then(|(_, a)| a.downcast_ref())
+            .or_else(|| self.invokers.get())
+    }
+
+    pub fn remove_qualified<I: 'static>(&mut self, qualifier: &Qualifier) -> bool {
+        self.qualified.remove(&(TypeId::of::<I>(), qualifier.clone())).is_some()
+    }
+
+    /// register an implementation of the service interface `S`, e.g. `dyn Greeter`
+    pub fn add_service<S>(&mut self, key: ServiceKey, service: Arc<S>) -> Option<Arc<S>>
+    where
+        S: ?Sized + 'static + Send + Sync
+    {
+        self.insert_qualified(Qualifier::Service(key), service)
+    }
+
+    /// the implementation of `S` for `key`, falling back to the same group without version
+    /// and then to the default service
+    pub fn get_service<S: ?Sized + 'static>(&self, key: &ServiceKey) -> Option<&Arc<S>> {
+        key.fallbacks().find_map(|key| {
+            self.qualified.get(&(TypeId::of::<Arc<S>>(), Qualifier::Service(key))).and_then(|(_, a)| a.downcast_ref())
+        })
+    }
+
+    /// every registration, unqualified ones with `None` as qualifier
+    pub(crate) fn registrations(&self) -> impl Iterator<Item = (TypeId, &'static str, Option<&Qualifier>, &dyn Any)> + '_ {
+        let unqualified = self.invokers.iter().map(|(id, name, a)| (id, name, None, a.as_ref() as &dyn Any));
+        let qualified = self.qualified.iter().map(|((id, qualifier), (name, a))| (*id, *name, Some(qualifier), a.as_ref() as &dyn Any));
+        unqualified.chain(qualified)
+    }
+
+    fn insert_qualified<T: 'static + Send + Sync>(&mut self, qualifier: Qualifier, value: T) -> Option<T> {
+        let old = self.qualified.insert((TypeId::of::<T>(), qualifier), (type_name::<T>(), Box::new(value)));
+        old.and_then(|(_, a)| a.downcast().ok()).map(|a| *a)
+    }
+
+    /// returns the invoker of the same type registered before, if any
+    pub fn add_invoker<Req, I>(&mut self, invoker: I) -> Option<I>
     where 
         I: Invoker<Req> + 'static + Send + Sync
     {
-        let id = TypeId::of::<I>();
-        self.invokers.insert(id, Box::new(invoker));
+        self.invokers.insert(invoker)
+    }
+
+    /// unregister `I`, returns whether it was registered
+    pub fn remove<I: 'static>(&mut self) -> bool {
+        self.invokers.take::<I>().is_some()
+    }
+
+    pub fn take<I: 'static>(&mut self) -> Option<I> {
+        self.invokers.take()
+    }
+
+    pub fn contains<I: 'static>(&self) -> bool {
+        self.invokers.contains::<I>()
+    }
+
+    pub fn entry<I: 'static + Send + Sync>(&mut self) -> Entry<'_, I> {
+        self.invokers.entry(None)
+    }
+
+    /// type names of the registered invokers, for debugging
+    pub fn type_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.invokers.type_names()
+    }
+
+    /// type names and qualifiers of the qualified registrations, for debugging
+    pub fn qualified_names(&self) -> impl Iterator<Item = (&'static str, &Qualifier)> + '_ {
+        self.qualified.iter().map(|((_, qualifier), (name, _))| (*name, qualifier))
     }
 
 }
@@ -74,13 +599,242 @@ impl InvokerManager {
 impl Typed for InvokerManager {
 
     fn get<I: 'static>(&self) -> Option<&I> {
-        let id = TypeId::of::<I>();
-        self.invokers.get(&id).map(|a| a.downcast_ref()).flatten()
+        self.invokers.get()
     }
 
     fn get_mut<I: 'static>(&mut self) -> Option<&mut I> {
-        let id = TypeId::of::<I>();
-        self.invokers.get_mut(&id).map(|a| a.downcast_mut()).flatten()
+        self.invokers.get_mut()
+    }
+}
+
+
+type ArcAny = Arc<dyn Any + Send + Sync>;
+
+#[derive(Clone)]
+struct Registration {
+    name: &'static str,
+    generation: u64,
+    invoker: ArcAny,
+}
+
+/// manager which is read without locking while invokers are added or replaced
+///
+/// readers load the current snapshot of the registered invokers, writers copy it, change
+/// the copy and publish it, one writer at a time. invokers are handed out as `Arc`, so an
+/// invoker replaced while a call is running lives until that call is done, see
+/// [`ConcurrentInvokerManager::swap`]. every registration gets a new generation number
+pub struct ConcurrentInvokerManager {
+    invokers: ArcSwap<HashMap<TypeId, Registration>>,
+    // next generation, also serializing writers
+    write: Mutex<u64>,
+}
+
+impl Default for ConcurrentInvokerManager {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ConcurrentInvokerManager {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.type_names()).finish()
+    }
+}
+
+impl ConcurrentInvokerManager {
+
+    pub fn new() -> Self {
+        Self { invokers: ArcSwap::from_pointee(HashMap::new()), write: Mutex::new(1) }
+    }
+
+    pub fn get<I: 'static + Send + Sync>(&self) -> Option<Arc<I>> {
+        self.get_versioned().map(|v| v.invoker)
+    }
+
+    /// the registered `I` along with its generation
+    pub fn get_versioned<I: 'static + Send + Sync>(&self) -> Option<Versioned<I>> {
+        let invokers = self.invokers.load();
+        let registration = invokers.get(&TypeId::of::<I>())?;
+        let invoker = registration.invoker.clone().downcast().ok()?;
+        Some(Versioned { generation: registration.generation, invoker })
+    }
+
+    pub fn generation<I: 'static>(&self) -> Option<u64> {
+        self.invokers.load().get(&TypeId::of::<I>()).map(|r| r.generation)
+    }
+
+    pub fn contains<I: 'static>(&self) -> bool {
+        self.invokers.load().contains_key(&TypeId::of::<I>())
+    }
+
+    /// returns the invoker of the same type registered before, if any
+    pub fn add_invoker<Req, I>(&self, invoker: I) -> Option<Arc<I>>
+    where
+        I: Invoker<Req> + 'static + Send + Sync
+    {
+        self.replace(invoker).map(|old| old.invoker)
+    }
+
+    /// replace the registered `I` by `invoker`, calls from now on go to the new one
+    ///
+    /// the returned [`Drain`] tells when the calls still holding the old one are done,
+    /// the old invoker is dropped right then
+    pub fn swap<Req, I>(&self, invoker: I) -> Option<Drain<I>>
+    where
+        I: Invoker<Req> + 'static + Send + Sync
+    {
+        self.replace(invoker).map(|old| Drain { generation: old.generation, invoker: Arc::downgrade(&old.invoker) })
+    }
+
+    /// unregister `I`, returning it
+    pub fn remove<I: 'static + Send + Sync>(&self) -> Option<Arc<I>> {
+        let old = self.update(|invokers, _| invokers.remove(&TypeId::of::<I>()));
+        old.and_then(|r| r.invoker.downcast().ok())
+    }
+
+    /// type names of the registered invokers, for debugging
+    pub fn type_names(&self) -> Vec<&'static str> {
+        self.invokers.load().values().map(|r| r.name).collect()
+    }
+
+    fn replace<I: 'static + Send + Sync>(&self, invoker: I) -> Option<Versioned<I>> {
+        let old = self.update(|invokers, generation| {
+            let registration = Registration { name: type_name::<I>(), generation, invoker: Arc::new(invoker) };
+            invokers.insert(TypeId::of::<I>(), registration)
+        });
+        let old = old?;
+        Some(Versioned { generation: old.generation, invoker: old.invoker.downcast().ok()? })
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut HashMap<TypeId, Registration>, u64) -> R) -> R {
+        let mut generation = self.write.lock().unwrap();
+        let mut invokers = HashMap::clone(&self.invokers.load());
+        let res = f(&mut invokers, *generation);
+        *generation += 1;
+        self.invokers.store(Arc::new(invokers));
+        res
+    }
+
+}
+
+
+/// an invoker of a [`ConcurrentInvokerManager`] and the generation it was registered with
+#[derive(Debug)]
+pub struct Versioned<I> {
+    generation: u64,
+    invoker: Arc<I>,
+}
+
+impl<I> Clone for Versioned<I> {
+
+    fn clone(&self) -> Self {
+        Self { generation: self.generation, invoker: self.invoker.clone() }
+    }
+}
+
+impl<I> Versioned<I> {
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn into_inner(self) -> Arc<I> {
+        self.invoker
+    }
+
+}
+
+impl<I> std::ops::Deref for Versioned<I> {
+
+    type Target = I;
+
+    fn deref(&self) -> &I {
+        &self.invoker
+    }
+}
+
+impl<Req, I: Invoker<Req>> Invoker<Req> for Versioned<I> {
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        self.invoker.invoke(context, req)
+    }
+}
+
+
+/// an invoker replaced by [`ConcurrentInvokerManager::swap`], drained once nobody holds it
+///
+/// calls hold the invoker while they run, async ones until their future is done only if
+/// they keep the handle they got from the manager alive that long
+#[derive(Debug)]
+pub struct Drain<I> {
+    generation: u64,
+    invoker: Weak<I>,
+}
+
+impl<I> Drain<I> {
+
+    /// generation of the replaced invoker
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// handles of the old invoker still alive
+    pub fn in_flight(&self) -> usize {
+        self.invoker.strong_count()
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.in_flight() == 0
+    }
+
+    /// block until drained or `timeout` is over, returns whether it's drained
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let until = Instant::now() + timeout;
+        while !self.is_drained() {
+            if Instant::now() >= until {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1).min(until - Instant::now()));
+        }
+        true
+    }
+
+}
+
+
+/// invoker looked up from a shared [`InvokerManager`] on every call
+///
+/// calls fail with [`InvokerError::NotRegistered`] while no `I` is registered
+pub struct Managed<I> {
+    manager: Arc<InvokerManager>,
+    _marker: PhantomData<fn() -> I>,
+}
+
+impl<I> Managed<I> {
+
+    pub fn new(manager: Arc<InvokerManager>) -> Self {
+        Self { manager, _marker: PhantomData }
+    }
+
+}
+
+impl<Req, I> Invoker<Req> for Managed<I>
+where
+    I: Invoker<Req> + 'static,
+    I::Res: FromInvokerError
+{
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        match self.manager.get::<I>() {
+            Some(invoker) => invoker.invoke(context, req),
+            None => Self::Res::from_invoker_error(InvokerError::NotRegistered(std::any::type_name::<I>())),
+        }
     }
 }
 
@@ -88,12 +842,12 @@ impl Typed for InvokerManager {
 #[cfg(test)]
 mod test {
 
-    use std::{any::Any, time::Duration, sync::Mutex};
+    use std::{sync::{Arc, Mutex}, time::Duration};
 
     use once_cell::sync::Lazy;
 
-    use crate::Typed;
-    use super::{Invoker, InvokeContext, InvokerManager};
+    use crate::{provide::{Demand, Provide}, Typed};
+    use super::{ConcurrentInvokerManager, Invoker, InvokeContext, InvokerManager, Merge, Qualifier, ServiceKey};
 
     static INVOKER_MANAGER: Lazy<Mutex<InvokerManager>> = Lazy::new(|| {
         let manager = InvokerManager::new();
@@ -102,12 +856,19 @@ mod test {
 
     struct InvokerFoo;
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     struct LocalContext {
         req_id: String,
         times: usize
     }
 
+    impl Merge for LocalContext {
+
+        fn merge(&mut self, base: Option<&Self>, other: Self) {
+            self.times += other.times - base.map(|b| b.times).unwrap_or(0);
+        }
+    }
+
     impl Invoker<String> for InvokerFoo {
 
         type Res = Result<String, String>;
@@ -128,6 +889,253 @@ mod test {
         INVOKER_MANAGER.lock().unwrap().add_invoker(InvokerFoo);
     }
 
+    #[test]
+    fn test_parent_context() {
+        let mut parent = InvokeContext::new();
+        parent.with_context(LocalContext { req_id: "parent".to_owned(), times: 0 });
+        parent.insert_cloneable(7u32);
+        parent.with_context(Mutex::new(0u8));
+        let parent = Arc::new(parent);
+
+        let mut child = InvokeContext::with_parent(parent.clone());
+        assert_eq!("parent", child.get::<LocalContext>().unwrap().req_id);
+        // inherited entries are copied on write
+        *child.get_mut::<u32>().unwrap() += 1;
+        // entries without a cloner can't be copied, inherited ones are read only
+        assert!(child.get_mut::<Mutex<u8>>().is_none());
+        assert!(child.get::<Mutex<u8>>().is_some());
+        child.with_context(LocalContext { req_id: "child".to_owned(), times: 1 });
+
+        let grandchild = InvokeContext::with_parent(Arc::new(child));
+        assert_eq!("child", grandchild.get::<LocalContext>().unwrap().req_id);
+        assert_eq!(8, *grandchild.get::<u32>().unwrap());
+        assert_eq!("parent", parent.get::<LocalContext>().unwrap().req_id);
+        assert_eq!(7, *parent.get::<u32>().unwrap());
+        assert!(grandchild.get::<String>().is_none());
+    }
+
+    #[test]
+    fn test_fork_join() {
+        let mut context = InvokeContext::new();
+        context.insert_cloneable(LocalContext { req_id: "req_id".to_owned(), times: 1 });
+
+        let children = (0..3).map(|_| {
+            let mut child = context.fork();
+            std::thread::spawn(move || {
+                let res = InvokerFoo.invoke(&mut child, "fan out".to_owned());
+                assert_eq!(Ok("req_id".to_owned()), res);
+                child
+            })
+        }).collect::<Vec<_>>();
+        // every fork shares the one snapshot
+        assert!(context.data.is_empty());
+        assert_eq!(4, Arc::strong_count(context.parent().unwrap()));
+        // the forking context keeps writing its entries
+        assert!(context.get_mut::<LocalContext>().is_some());
+
+        for child in children {
+            let mut child = child.join().unwrap();
+            assert!(context.join::<LocalContext>(&mut child));
+            assert!(!context.join::<LocalContext>(&mut child));
+        }
+        assert_eq!(4, context.get::<LocalContext>().unwrap().times);
+    }
+
+    #[test]
+    fn test_map_api() {
+        let mut parent = InvokeContext::new();
+        assert_eq!(None, parent.insert(1u32));
+        assert_eq!(Some(1), parent.insert(2u32));
+        let mut context = InvokeContext::with_parent(Arc::new(parent));
+
+        *context.entry::<u32>().or_insert(10) += 1;
+        context.entry::<String>().or_insert_with(|| "a".to_owned()).push('b');
+        assert_eq!("ab", context.entry::<String>().or_default());
+        let mut names = context.type_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(vec!["alloc::string::String", "u32"], names);
+        assert_eq!("{\"u32\"}", format!("{:?}", context.parent().unwrap().data));
+
+        assert_eq!(Some(11), context.take::<u32>());
+        // the parent's entry shows through again
+        assert!(context.contains::<u32>());
+        assert_eq!(2, *context.get::<u32>().unwrap());
+        assert!(context.remove::<String>());
+        assert!(!context.remove::<String>());
+        assert!(!context.contains::<String>());
+
+        let mut manager = InvokerManager::new();
+        assert!(manager.add_invoker(InvokerFoo).is_none());
+        assert!(manager.add_invoker(InvokerFoo).is_some());
+        assert_eq!(vec![std::any::type_name::<InvokerFoo>()], manager.type_names().collect::<Vec<_>>());
+        assert!(manager.take::<InvokerFoo>().is_some());
+        assert!(!manager.contains::<InvokerFoo>());
+        manager.entry().or_insert(InvokerFoo);
+        assert!(manager.remove::<InvokerFoo>());
+    }
+
+    #[test]
+    fn test_spilled_map() {
+        let mut context = InvokeContext::new();
+        context.insert(0u8);
+        context.insert(1u16);
+        context.insert(2u32);
+        context.insert(3u64);
+        context.insert(4i8);
+        context.insert(5i16);
+        context.insert(6i32);
+        context.insert(7i64);
+        assert!(matches!(context.data.storage, super::Storage::Inline(_)));
+        context.insert("8".to_owned());
+        assert!(matches!(context.data.storage, super::Storage::Spilled(_)));
+
+        assert_eq!(Some(2), context.insert(20u32));
+        assert_eq!(20, *context.get::<u32>().unwrap());
+        assert_eq!("8", context.get::<String>().unwrap());
+        assert_eq!(Some(7), context.take::<i64>());
+        assert_eq!(8, context.type_names().count());
+    }
+
+    trait Named {
+        fn name(&self) -> &str;
+    }
+
+    struct User(String);
+
+    impl Named for User {
+        fn name(&self) -> &str {
+            &self.0
+        }
+    }
+
+    impl Provide for User {
+        fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
+            demand.provide_ref::<dyn Named>(self).provide_ref::<str>(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_provider() {
+        let mut parent = InvokeContext::new();
+        parent.with_provider(User("parent".to_owned()));
+        let mut context = InvokeContext::with_parent(Arc::new(parent));
+        assert_eq!("parent", context.request_ref::<dyn Named>().unwrap().name());
+
+        context.with_provider(User("child".to_owned()));
+        assert_eq!("child", context.request_ref::<dyn Named>().unwrap().name());
+        assert_eq!("child", context.request_ref::<str>().unwrap());
+        assert!(context.request_ref::<u32>().is_none());
+    }
+
+    #[test]
+    fn test_concurrent_manager() {
+        let manager = Arc::new(ConcurrentInvokerManager::new());
+        assert!(manager.add_invoker(InvokerFoo).is_none());
+
+        let readers = (0..4).map(|_| {
+            let manager = manager.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    let invoker = manager.get::<InvokerFoo>().unwrap();
+                    let mut context = InvokeContext::new();
+                    context.with_context(LocalContext { req_id: "req_id".to_owned(), times: 0 });
+                    assert_eq!(Ok("req_id".to_owned()), invoker.invoke(&mut context, "req".to_owned()));
+                }
+            })
+        }).collect::<Vec<_>>();
+        // replaced while being read
+        for _ in 0..100 {
+            let old = manager.add_invoker(InvokerFoo);
+            assert!(old.is_some());
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let held = manager.get::<InvokerFoo>().unwrap();
+        assert!(manager.remove::<InvokerFoo>().is_some());
+        assert!(!manager.contains::<InvokerFoo>());
+        // calls holding the removed invoker still go through
+        let mut context = InvokeContext::new();
+        context.with_context(LocalContext { req_id: "held".to_owned(), times: 0 });
+        assert_eq!(Ok("held".to_owned()), held.invoke(&mut context, "req".to_owned()));
+    }
+
+    struct Echo(&'static str);
+
+    impl Invoker<String> for Echo {
+
+        type Res = Result<String, String>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: String) -> Self::Res {
+            Ok(format!("{} {}", self.0, req))
+        }
+    }
+
+    #[test]
+    fn test_qualified() {
+        let mut manager = InvokerManager::new();
+        manager.add_invoker(Echo("default"));
+        assert!(manager.add_qualified("primary", Echo("primary")).is_none());
+        assert!(manager.add_qualified("backup", Echo("backup")).is_none());
+
+        assert_eq!("primary", manager.get_qualified::<Echo>(&"primary".into()).unwrap().0);
+        assert_eq!("backup", manager.get_qualified::<Echo>(&"backup".into()).unwrap().0);
+        assert_eq!("default", manager.get_qualified::<Echo>(&"other".into()).unwrap().0);
+        assert!(manager.remove_qualified::<Echo>(&"backup".into()));
+        assert_eq!("default", manager.get_qualified::<Echo>(&"backup".into()).unwrap().0);
+        assert_eq!(1, manager.qualified_names().count());
+    }
+
+    trait Greeter: Send + Sync {
+        fn greet(&self) -> String;
+    }
+
+    impl Greeter for Echo {
+        fn greet(&self) -> String {
+            self.0.to_owned()
+        }
+    }
+
+    #[test]
+    fn test_service() {
+        let mut manager = InvokerManager::new();
+        assert!(manager.get_service::<dyn Greeter>(&ServiceKey::default()).is_none());
+        manager.add_service::<dyn Greeter>(ServiceKey::default(), Arc::new(Echo("default")));
+        manager.add_service::<dyn Greeter>(ServiceKey::group("gray"), Arc::new(Echo("gray")));
+        manager.add_service::<dyn Greeter>(ServiceKey::new("gray", "2.0"), Arc::new(Echo("gray 2.0")));
+
+        let greet = |key: ServiceKey| manager.get_service::<dyn Greeter>(&key).unwrap().greet();
+        assert_eq!("gray 2.0", greet(ServiceKey::new("gray", "2.0")));
+        assert_eq!("gray", greet(ServiceKey::new("gray", "1.0")));
+        assert_eq!("default", greet(ServiceKey::new("blue", "2.0")));
+        assert!(matches!(manager.qualified_names().next().unwrap().1, Qualifier::Service(_)));
+    }
+
+    #[test]
+    fn test_hot_swap() {
+        let manager = ConcurrentInvokerManager::new();
+        assert!(manager.swap(Echo("v1")).is_none());
+        let in_flight = manager.get_versioned::<Echo>().unwrap();
+        let generation = in_flight.generation();
+        assert_eq!(Some(generation), manager.generation::<Echo>());
+
+        let drain = manager.swap(Echo("v2")).unwrap();
+        assert_eq!(generation, drain.generation());
+        assert!(manager.generation::<Echo>().unwrap() > generation);
+        assert_eq!(Ok("v2 req".to_owned()), manager.get::<Echo>().unwrap().invoke(&mut InvokeContext::new(), "req".to_owned()));
+
+        // the call on the old invoker finishes on it
+        assert_eq!(1, drain.in_flight());
+        assert!(!drain.wait(Duration::from_millis(5)));
+        let call = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            in_flight.invoke(&mut InvokeContext::new(), "req".to_owned())
+        });
+        assert!(drain.wait(Duration::from_secs(5)));
+        assert_eq!(Ok("v1 req".to_owned()), call.join().unwrap());
+    }
+
     #[test]
     fn test_invoker() {
         init();
@@ -139,8 +1147,10 @@ mod test {
         let guard = INVOKER_MANAGER.lock().unwrap();
         let invoker = guard.get::<InvokerFoo>().unwrap();
         let res = invoker.invoke(&mut context, "this is req".to_owned());
+        assert_eq!(Ok("req_id".to_owned()), res);
         assert_eq!(1, context.get::<LocalContext>().unwrap().times);
         let res = guard.get::<InvokerFoo>().unwrap().invoke(&mut context, "req2".into());
+        assert_eq!(Ok("req_id".to_owned()), res);
         assert_eq!(2, context.get::<LocalContext>().unwrap().times);
     }
 
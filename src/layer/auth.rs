@@ -0,0 +1,76 @@
+use crate::{context::Scopes, describe::{Describe, Description}, error::{FromInvokerError, InvokerError}, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// rejects callers which weren't granted the `auth_scope` in the `MethodDefInfo` of `M`
+///
+/// the granted scopes are the [`Scopes`] entry of the context, put there by whoever
+/// authenticated the caller. calls without the scope fail with
+/// [`InvokerError::PermissionDenied`], methods without a scope are open to everyone
+pub struct Auth<M, I> {
+    method: M,
+    inner: I,
+}
+
+impl<M: MethodDef, I> Auth<M, I> {
+
+    pub fn new(method: M, inner: I) -> Self {
+        Self { method, inner }
+    }
+
+}
+
+impl<M, I> Invoker<M::Request> for Auth<M, I>
+where
+    M: MethodDef,
+    I: Invoker<M::Request>,
+    I::Res: FromInvokerError
+{
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        if let Some(scope) = &self.method.get_method_def_info().auth_scope {
+            if !context.get::<Scopes>().map(|s| s.contains(scope)).unwrap_or(false) {
+                return I::Res::from_invoker_error(InvokerError::PermissionDenied { method: M::NAME, scope: scope.clone() });
+            }
+        }
+        self.inner.invoke(context, req)
+    }
+}
+
+impl<M, I: Describe> Describe for Auth<M, I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("auth")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use serde_json::Value;
+
+    use crate::{context::Scopes, error::{ErrorCode, InvokerError}, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker}, layer::{fallback::StaticResponse, test_method::GetMethod}};
+    use super::Auth;
+
+    #[test]
+    fn test_auth() {
+        let info = MethodDefInfo { auth_scope: Some("admin".to_owned()), ..MethodDefInfo::default() };
+        let auth = Auth::new(GetMethod::new(info), StaticResponse::new(true));
+
+        let res: Result<bool, InvokerError> = auth.invoke(&mut InvokeContext::new(), Value::Null);
+        assert_eq!(ErrorCode::PermissionDenied, res.unwrap_err().code());
+
+        let mut context = InvokeContext::new();
+        context.with_context(Scopes::new(["read"]));
+        assert!(auth.invoke(&mut context, Value::Null).is_err());
+        context.with_context(Scopes::new(["read", "admin"]));
+        assert!(auth.invoke(&mut context, Value::Null).unwrap());
+
+        // no scope required
+        let open = Auth::new(GetMethod::new(MethodDefInfo::default()), StaticResponse::new(true));
+        assert!(open.invoke(&mut InvokeContext::new(), Value::Null).unwrap());
+    }
+
+}
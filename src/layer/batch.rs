@@ -0,0 +1,271 @@
+use std::{future::Future, mem, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use futures_channel::oneshot;
+use futures_util::{future::{select, BoxFuture, Either, Shared}, FutureExt};
+
+use crate::{context::Method, describe::{Describe, Description}, error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}, timer::Delay};
+
+
+/// a method taking many items at once, with the way single items are folded into its request
+/// and its response is split back out
+pub trait BatchMethod: MethodDef {
+
+    type Item: Send + 'static;
+
+    type ItemResponse: Send + 'static;
+
+    fn batch(items: Vec<Self::Item>) -> Self::Request;
+
+    /// one result per item, in the order of the items
+    fn split(res: Self::Response) -> Vec<Result<Self::ItemResponse, InvokerError>>;
+
+}
+
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// a batch is sent as soon as it holds this many items
+    pub max_size: usize,
+    /// longest time the first item of a batch waits for others
+    pub window: Duration,
+}
+
+
+type Results<R> = Arc<Vec<Mutex<Option<Result<R, InvokerError>>>>>;
+
+type Flush<R> = Shared<BoxFuture<'static, Result<Results<R>, InvokerError>>>;
+
+struct Pending<T, R> {
+    id: u64,
+    /// end of the window of batch `id`
+    flush_at: Instant,
+    items: Vec<T>,
+    waiters: Vec<oneshot::Sender<(Flush<R>, usize)>>,
+}
+
+struct BatchShared<B: BatchMethod, I> {
+    inner: I,
+    config: BatchConfig,
+    pending: Mutex<Pending<B::Item, B::ItemResponse>>,
+}
+
+impl<B, I, F> BatchShared<B, I>
+where
+    B: BatchMethod,
+    I: Invoker<B::Request, Res = F>,
+    F: Future<Output = Result<B::Response, InvokerError>> + Send + 'static
+{
+
+    /// send the pending batch, only if it still is batch `id` when one is given
+    fn flush(&self, id: Option<u64>) {
+        let (items, waiters) = {
+            let mut pending = self.pending.lock().unwrap();
+            if id.map(|id| id != pending.id).unwrap_or(false) {
+                return;
+            }
+            pending.id += 1;
+            (mem::take(&mut pending.items), mem::take(&mut pending.waiters))
+        };
+        if items.is_empty() {
+            return;
+        }
+
+        let len = items.len();
+        let mut context = InvokeContext::new();
+        context.with_context(Method::of::<B>());
+        let fut = self.inner.invoke(&mut context, B::batch(items));
+        let flush: Flush<B::ItemResponse> = async move {
+            let results = B::split(fut.await?);
+            if results.len() != len {
+                let e = anyhow::anyhow!("batch `{}` answered {} items, expected {}", B::NAME, results.len(), len);
+                return Err(e.into());
+            }
+            Ok(Arc::new(results.into_iter().map(|r| Mutex::new(Some(r))).collect()))
+        }.boxed().shared();
+        for (i, waiter) in waiters.into_iter().enumerate() {
+            let _ = waiter.send((flush.clone(), i));
+        }
+    }
+
+}
+
+
+/// collects single calls into batches of the user supplied [`BatchMethod`] `B`
+///
+/// a batch is sent when it is full or its window is over, whatever comes first, and each
+/// caller's future resolves with the result of its own item. failures of the whole batch
+/// are handed to every caller. once the window is over, the first of its callers to be
+/// polled sends the batch, so the inner invoker never runs on the timer thread
+///
+/// batched calls share one context holding the [`Method`] of `B`, the contexts of the
+/// single calls are not passed on
+pub struct Batch<B: BatchMethod, I> {
+    shared: Arc<BatchShared<B, I>>,
+}
+
+impl<B: BatchMethod, I> Batch<B, I> {
+
+    pub fn new(inner: I, config: BatchConfig) -> Self {
+        let pending = Pending { id: 0, flush_at: Instant::now(), items: Vec::new(), waiters: Vec::new() };
+        Self { shared: Arc::new(BatchShared { inner, config, pending: Mutex::new(pending) }) }
+    }
+
+}
+
+impl<B, I, F> Invoker<B::Item> for Batch<B, I>
+where
+    B: BatchMethod + 'static,
+    I: Invoker<B::Request, Res = F> + Send + Sync + 'static,
+    F: Future<Output = Result<B::Response, InvokerError>> + Send + 'static
+{
+
+    type Res = InvokerFuture<B::ItemResponse>;
+
+    fn invoke(&self, _context: &mut InvokeContext, item: B::Item) -> Self::Res {
+        let (tx, rx) = oneshot::channel();
+        let (full, id, flush_at) = {
+            let mut pending = self.shared.pending.lock().unwrap();
+            if pending.items.is_empty() {
+                pending.flush_at = Instant::now() + self.shared.config.window;
+            }
+            pending.items.push(item);
+            pending.waiters.push(tx);
+            (pending.items.len() >= self.shared.config.max_size, pending.id, pending.flush_at)
+        };
+        if full {
+            self.shared.flush(None);
+        }
+
+        let shared = self.shared.clone();
+        InvokerFuture::new(async move {
+            let received = match select(rx, Delay::until(flush_at)).await {
+                Either::Left((received, _)) => received,
+                Either::Right(((), rx)) => {
+                    // a no-op if another caller sent the batch already
+                    shared.flush(Some(id));
+                    rx.await
+                },
+            };
+            let (flush, i) = received.map_err(|_| anyhow::anyhow!("batch `{}` dropped", B::NAME))?;
+            let results = flush.await?;
+            let res = results[i].lock().unwrap().take();
+            res.unwrap_or_else(|| Err(anyhow::anyhow!("batch result taken twice").into()))
+        })
+    }
+}
+
+impl<B: BatchMethod, I: Describe> Describe for Batch<B, I> {
+
+    fn describe(&self) -> Description {
+        self.shared.inner.describe().layer("batch")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::Mutex, thread::{self, ThreadId}, time::{Duration, Instant}};
+
+    use futures_executor::block_on;
+    use futures_util::future::join_all;
+    use serde_json::{json, Value};
+
+    use crate::{error::{ErrorCode, InvokerError}, future::InvokerFuture, invoker::invoker5::{MethodDef, MethodDefInfo}, invoker_manager::{InvokeContext, Invoker}};
+    use super::{Batch, BatchConfig, BatchMethod};
+
+    struct BatchDouble {
+        info: MethodDefInfo,
+    }
+
+    impl MethodDef for BatchDouble {
+
+        const NAME: &'static str = "batchDouble";
+
+        type Request = Value;
+
+        type Response = Value;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+    impl BatchMethod for BatchDouble {
+
+        type Item = u64;
+
+        type ItemResponse = u64;
+
+        fn batch(items: Vec<u64>) -> Value {
+            json!(items)
+        }
+
+        fn split(res: Value) -> Vec<Result<u64, InvokerError>> {
+            res.as_array().unwrap().iter().map(|v| {
+                v.as_u64().ok_or_else(|| InvokerError::from(anyhow::anyhow!("no value")))
+            }).collect()
+        }
+    }
+
+    /// doubles every item, `0` has no answer and `13` fails the whole batch
+    #[derive(Default)]
+    struct StorageInvoker {
+        batches: Mutex<Vec<usize>>,
+        threads: Mutex<Vec<ThreadId>>,
+    }
+
+    impl Invoker<Value> for StorageInvoker {
+
+        type Res = InvokerFuture<Value>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Value) -> Self::Res {
+            let items = req.as_array().unwrap().iter().map(|v| v.as_u64().unwrap()).collect::<Vec<_>>();
+            self.batches.lock().unwrap().push(items.len());
+            self.threads.lock().unwrap().push(thread::current().id());
+            InvokerFuture::new(async move {
+                if items.contains(&13) {
+                    return Err(InvokerError::NoEndpoint);
+                }
+                Ok(json!(items.iter().map(|i| if *i == 0 { Value::Null } else { json!(i * 2) }).collect::<Vec<_>>()))
+            })
+        }
+    }
+
+    #[test]
+    fn test_flush_on_size() {
+        let batch = Batch::<BatchDouble, _>::new(StorageInvoker::default(), BatchConfig { max_size: 3, window: Duration::from_secs(10) });
+        let futs = [1, 0, 2].into_iter().map(|i| batch.invoke(&mut InvokeContext::new(), i)).collect::<Vec<_>>();
+        let res = block_on(join_all(futs));
+
+        assert_eq!(2, *res[0].as_ref().unwrap());
+        // only the item without an answer fails
+        assert!(res[1].is_err());
+        assert_eq!(4, *res[2].as_ref().unwrap());
+        assert_eq!(vec![3], *batch.shared.inner.batches.lock().unwrap());
+    }
+
+    #[test]
+    fn test_flush_on_window() {
+        let batch = Batch::<BatchDouble, _>::new(StorageInvoker::default(), BatchConfig { max_size: 100, window: Duration::from_millis(20) });
+        let start = Instant::now();
+        let futs = [1, 2].into_iter().map(|i| batch.invoke(&mut InvokeContext::new(), i)).collect::<Vec<_>>();
+        let res = block_on(join_all(futs)).into_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(vec![2, 4], res);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(vec![2], *batch.shared.inner.batches.lock().unwrap());
+        // sent by the caller, not the timer thread
+        assert_eq!(vec![thread::current().id()], *batch.shared.inner.threads.lock().unwrap());
+    }
+
+    #[test]
+    fn test_batch_failure() {
+        let batch = Batch::<BatchDouble, _>::new(StorageInvoker::default(), BatchConfig { max_size: 2, window: Duration::from_secs(10) });
+        let futs = [1, 13].into_iter().map(|i| batch.invoke(&mut InvokeContext::new(), i)).collect::<Vec<_>>();
+        for res in block_on(join_all(futs)) {
+            assert_eq!(ErrorCode::Unavailable, res.unwrap_err().code());
+        }
+    }
+
+}
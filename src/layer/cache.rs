@@ -0,0 +1,334 @@
+use std::{collections::{hash_map::DefaultHasher, BTreeMap, HashMap}, hash::{Hash, Hasher}, sync::Mutex, time::{Duration, Instant}};
+
+use crate::{context::CacheBypass, describe::{Describe, Description}, error::InvokerError, invoker::invoker5::{Encoder, Message, MethodDef}, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// requests which can be told apart by their encoded bytes
+pub trait EncodedKey {
+
+    /// bytes of the encoded request, `None` if it can't be encoded
+    fn encoded(&self) -> Option<Vec<u8>>;
+
+}
+
+impl<T> EncodedKey for T
+where
+    T: Message + Clone,
+    T::Encoder: Default,
+    <T::Encoder as Encoder<T>>::Message: AsRef<[u8]>
+{
+
+    fn encoded(&self) -> Option<Vec<u8>> {
+        let encoder = T::Encoder::default();
+        encoder.encode(self.clone()).ok().map(|m| m.as_ref().to_vec())
+    }
+}
+
+
+/// `MethodDef::NAME` plus the hash of the encoded request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestKey {
+    pub method: &'static str,
+    pub hash: u64,
+}
+
+impl RequestKey {
+
+    pub fn of<M>(req: &M::Request) -> Option<Self>
+    where
+        M: MethodDef,
+        M::Request: EncodedKey
+    {
+        let bytes = req.encoded()?;
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        Some(Self { method: M::NAME, hash: hasher.finish() })
+    }
+
+}
+
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// how long a response is fresh
+    pub ttl: Duration,
+    /// how long after `ttl` a stale response may still be served while one caller revalidates it
+    pub stale_while_revalidate: Duration,
+    /// entries kept, least recently used ones are evicted first
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+
+    fn default() -> Self {
+        Self { ttl: Duration::from_secs(60), stale_while_revalidate: Duration::ZERO, max_entries: 1024 }
+    }
+}
+
+
+struct Entry<T> {
+    value: T,
+    stored_at: Instant,
+    used: u64,
+    revalidating: bool,
+}
+
+struct Lru<T> {
+    entries: HashMap<RequestKey, Entry<T>>,
+    // last use tick -> key, the first one is evicted
+    order: BTreeMap<u64, RequestKey>,
+    tick: u64,
+}
+
+impl<T> Lru<T> {
+
+    fn touch(&mut self, key: &RequestKey) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.tick += 1;
+            self.order.remove(&entry.used);
+            entry.used = self.tick;
+            self.order.insert(self.tick, *key);
+        }
+    }
+
+    fn insert(&mut self, key: RequestKey, value: T, max_entries: usize) {
+        self.tick += 1;
+        let entry = Entry { value, stored_at: Instant::now(), used: self.tick, revalidating: false };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.order.remove(&old.used);
+        }
+        self.order.insert(self.tick, key);
+        while self.entries.len() > max_entries {
+            match self.order.pop_first() {
+                Some((_, key)) => { self.entries.remove(&key); },
+                None => break,
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &RequestKey) {
+        if let Some(old) = self.entries.remove(key) {
+            self.order.remove(&old.used);
+        }
+    }
+
+}
+
+
+enum Lookup<T> {
+    Fresh(T),
+    Stale(T),
+    Revalidate(Option<T>),
+}
+
+/// caches successful responses of methods whose `MethodDefInfo` marks them cacheable
+///
+/// responses are keyed by [`RequestKey`]. within the stale-while-revalidate window the first
+/// caller refreshes the entry while the others are served the stale response right away,
+/// the stale response is also served if that refresh fails. calls with a [`CacheBypass`]
+/// entry in the context always go to the inner invoker
+pub struct Cache<M, I, T> {
+    method: M,
+    inner: I,
+    config: CacheConfig,
+    lru: Mutex<Lru<T>>,
+}
+
+impl<M: MethodDef, I, T: Clone> Cache<M, I, T> {
+
+    pub fn new(method: M, inner: I, config: CacheConfig) -> Self {
+        let lru = Lru { entries: HashMap::new(), order: BTreeMap::new(), tick: 0 };
+        Self { method, inner, config, lru: Mutex::new(lru) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn invalidate(&self, key: &RequestKey) {
+        self.lru.lock().unwrap().remove(key);
+    }
+
+    fn lookup(&self, key: &RequestKey) -> Lookup<T> {
+        let mut lru = self.lru.lock().unwrap();
+        let lookup = match lru.entries.get_mut(key) {
+            None => return Lookup::Revalidate(None),
+            Some(entry) => {
+                let age = entry.stored_at.elapsed();
+                if age < self.config.ttl {
+                    Lookup::Fresh(entry.value.clone())
+                } else if age < self.config.ttl + self.config.stale_while_revalidate {
+                    if entry.revalidating {
+                        Lookup::Stale(entry.value.clone())
+                    } else {
+                        entry.revalidating = true;
+                        Lookup::Revalidate(Some(entry.value.clone()))
+                    }
+                } else {
+                    Lookup::Revalidate(None)
+                }
+            },
+        };
+        lru.touch(key);
+        lookup
+    }
+
+}
+
+impl<M, I, T> Invoker<M::Request> for Cache<M, I, T>
+where
+    M: MethodDef,
+    M::Request: EncodedKey,
+    I: Invoker<M::Request, Res = Result<T, InvokerError>>,
+    T: Clone
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        if !self.method.get_method_def_info().is_cacheable() {
+            return self.inner.invoke(context, req);
+        }
+        let key = match RequestKey::of::<M>(&req) {
+            Some(key) => key,
+            None => return self.inner.invoke(context, req),
+        };
+
+        let stale = if context.get::<CacheBypass>().is_some() {
+            None
+        } else {
+            match self.lookup(&key) {
+                Lookup::Fresh(value) | Lookup::Stale(value) => return Ok(value),
+                Lookup::Revalidate(stale) => stale,
+            }
+        };
+
+        let res = self.inner.invoke(context, req);
+        let mut lru = self.lru.lock().unwrap();
+        match (res, stale) {
+            (Ok(value), _) => {
+                lru.insert(key, value.clone(), self.config.max_entries);
+                Ok(value)
+            },
+            (Err(_), Some(stale)) => {
+                if let Some(entry) = lru.entries.get_mut(&key) {
+                    entry.revalidating = false;
+                }
+                Ok(stale)
+            },
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+impl<M, I: Describe, T> Describe for Cache<M, I, T> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("cache")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, mpsc, Arc, Mutex}, thread, time::Duration};
+
+    use serde_json::{json, Value};
+
+    use crate::{context::CacheBypass, error::InvokerError, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker}, layer::test_method::GetMethod};
+    use super::{Cache, CacheConfig, RequestKey};
+
+    fn method(cacheable: bool) -> GetMethod {
+        GetMethod::new(MethodDefInfo { cacheable, ..MethodDefInfo::default() })
+    }
+
+    /// answers with the number of calls so far, waits for the gate when asked for `"wait"`
+    #[derive(Default)]
+    struct CountingInvoker {
+        calls: AtomicUsize,
+        gate: Mutex<Option<mpsc::Receiver<()>>>,
+    }
+
+    impl Invoker<Value> for CountingInvoker {
+
+        type Res = Result<usize, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Value) -> Self::Res {
+            if req == json!("wait") {
+                let gate = self.gate.lock().unwrap().take().unwrap();
+                gate.recv().unwrap();
+            }
+            Ok(self.calls.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let config = CacheConfig { max_entries: 2, ..CacheConfig::default() };
+        let cache = Cache::new(method(true), CountingInvoker::default(), config);
+        let mut context = InvokeContext::new();
+
+        assert_eq!(1, cache.invoke(&mut context, json!({"id": 1})).unwrap());
+        assert_eq!(1, cache.invoke(&mut context, json!({"id": 1})).unwrap());
+        assert_eq!(2, cache.invoke(&mut context, json!({"id": 2})).unwrap());
+
+        let mut bypass = InvokeContext::new();
+        bypass.with_context(CacheBypass);
+        assert_eq!(3, cache.invoke(&mut bypass, json!({"id": 1})).unwrap());
+        assert_eq!(3, cache.invoke(&mut context, json!({"id": 1})).unwrap());
+
+        // {"id": 2} is the least recently used one
+        assert_eq!(4, cache.invoke(&mut context, json!({"id": 3})).unwrap());
+        assert_eq!(2, cache.len());
+        assert_eq!(3, cache.invoke(&mut context, json!({"id": 1})).unwrap());
+        assert_eq!(5, cache.invoke(&mut context, json!({"id": 2})).unwrap());
+
+        cache.invalidate(&RequestKey::of::<GetMethod>(&json!({"id": 2})).unwrap());
+        assert_eq!(6, cache.invoke(&mut context, json!({"id": 2})).unwrap());
+    }
+
+    #[test]
+    fn test_not_cacheable() {
+        let cache = Cache::new(method(false), CountingInvoker::default(), CacheConfig::default());
+        assert_eq!(1, cache.invoke(&mut InvokeContext::new(), json!(1)).unwrap());
+        assert_eq!(2, cache.invoke(&mut InvokeContext::new(), json!(1)).unwrap());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_stale_while_revalidate() {
+        let config = CacheConfig { ttl: Duration::from_millis(20), stale_while_revalidate: Duration::from_secs(10), max_entries: 10 };
+        let cache = Arc::new(Cache::new(method(true), CountingInvoker::default(), config));
+        let open_gate = || {
+            let (tx, rx) = mpsc::channel();
+            *cache.inner.gate.lock().unwrap() = Some(rx);
+            tx
+        };
+
+        open_gate().send(()).unwrap();
+        assert_eq!(1, cache.invoke(&mut InvokeContext::new(), json!("wait")).unwrap());
+        thread::sleep(Duration::from_millis(30));
+
+        // the first caller after the ttl revalidates, blocked on the gate
+        let gate = open_gate();
+        let revalidating = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.invoke(&mut InvokeContext::new(), json!("wait")))
+        };
+        while cache.inner.gate.lock().unwrap().is_some() {
+            thread::yield_now();
+        }
+        // meanwhile the stale response is served
+        assert_eq!(1, cache.invoke(&mut InvokeContext::new(), json!("wait")).unwrap());
+
+        gate.send(()).unwrap();
+        assert_eq!(2, revalidating.join().unwrap().unwrap());
+        assert_eq!(2, cache.invoke(&mut InvokeContext::new(), json!("wait")).unwrap());
+    }
+
+}
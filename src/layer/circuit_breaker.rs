@@ -0,0 +1,380 @@
+use std::{collections::{HashMap, VecDeque}, sync::Mutex, time::{Duration, Instant}};
+
+use crate::{context::Method, describe::{Describe, Description}, error::InvokerError, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// failed calls / calls in the window at which the circuit opens, in `0.0..=1.0`
+    pub failure_rate_threshold: f64,
+    /// slow calls / calls in the window at which the circuit opens, in `0.0..=1.0`
+    pub slow_call_rate_threshold: f64,
+    /// calls taking at least this long count as slow
+    pub slow_call_duration: Duration,
+    /// number of most recent calls the rates are computed over
+    pub window_size: usize,
+    /// rates are not evaluated until the window holds this many calls
+    pub minimum_calls: usize,
+    /// how long the circuit stays open before letting trial calls through
+    pub open_duration: Duration,
+    /// number of trial calls in half open state
+    pub half_open_calls: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+
+    fn default() -> Self {
+        Self {
+            failure_rate_threshold: 0.5,
+            slow_call_rate_threshold: 1.0,
+            slow_call_duration: Duration::from_secs(1),
+            window_size: 100,
+            minimum_calls: 10,
+            open_duration: Duration::from_secs(30),
+            half_open_calls: 5,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub method: &'static str,
+    pub from: CircuitState,
+    pub to: CircuitState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitMetrics {
+    pub state: CircuitState,
+    pub successful_calls: u64,
+    pub failed_calls: u64,
+    pub slow_calls: u64,
+    pub rejected_calls: u64,
+    /// failure rate over the current window
+    pub failure_rate: f64,
+    /// slow call rate over the current window
+    pub slow_call_rate: f64,
+}
+
+
+#[derive(Clone, Copy)]
+struct Outcome {
+    failed: bool,
+    slow: bool,
+}
+
+struct Breaker {
+    config: CircuitBreakerConfig,
+    state: CircuitState,
+    window: VecDeque<Outcome>,
+    opened_at: Instant,
+    half_open_permits: usize,
+    metrics: CircuitMetrics,
+}
+
+impl Breaker {
+
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: CircuitState::Closed,
+            window: VecDeque::new(),
+            opened_at: Instant::now(),
+            half_open_permits: 0,
+            metrics: CircuitMetrics {
+                state: CircuitState::Closed,
+                successful_calls: 0,
+                failed_calls: 0,
+                slow_calls: 0,
+                rejected_calls: 0,
+                failure_rate: 0.0,
+                slow_call_rate: 0.0,
+            },
+        }
+    }
+
+    fn rates(&self) -> (f64, f64) {
+        if self.window.is_empty() {
+            return (0.0, 0.0);
+        }
+        let len = self.window.len() as f64;
+        let failed = self.window.iter().filter(|o| o.failed).count() as f64;
+        let slow = self.window.iter().filter(|o| o.slow).count() as f64;
+        (failed / len, slow / len)
+    }
+
+    fn exceeds_thresholds(&self) -> bool {
+        let (failure_rate, slow_call_rate) = self.rates();
+        failure_rate >= self.config.failure_rate_threshold || slow_call_rate >= self.config.slow_call_rate_threshold
+    }
+
+    fn transition(&mut self, to: CircuitState) -> (CircuitState, CircuitState) {
+        let from = self.state;
+        self.state = to;
+        self.metrics.state = to;
+        self.window.clear();
+        match to {
+            CircuitState::Open => self.opened_at = Instant::now(),
+            CircuitState::HalfOpen => self.half_open_permits = self.config.half_open_calls,
+            CircuitState::Closed => {},
+        }
+        (from, to)
+    }
+
+    /// whether a call may go through, with the transition caused by asking
+    fn acquire(&mut self) -> (bool, Option<(CircuitState, CircuitState)>) {
+        let mut transition = None;
+        if self.state == CircuitState::Open && self.opened_at.elapsed() >= self.config.open_duration {
+            transition = Some(self.transition(CircuitState::HalfOpen));
+        }
+        let permitted = match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen if self.half_open_permits > 0 => {
+                self.half_open_permits -= 1;
+                true
+            },
+            CircuitState::HalfOpen => false,
+        };
+        if !permitted {
+            self.metrics.rejected_calls += 1;
+        }
+        (permitted, transition)
+    }
+
+    fn record(&mut self, outcome: Outcome) -> Option<(CircuitState, CircuitState)> {
+        if outcome.failed {
+            self.metrics.failed_calls += 1;
+        } else {
+            self.metrics.successful_calls += 1;
+        }
+        if outcome.slow {
+            self.metrics.slow_calls += 1;
+        }
+        // the circuit was tripped by another call meanwhile
+        if self.state == CircuitState::Open {
+            return None;
+        }
+
+        self.window.push_back(outcome);
+        while self.window.len() > self.config.window_size {
+            self.window.pop_front();
+        }
+        let (failure_rate, slow_call_rate) = self.rates();
+        self.metrics.failure_rate = failure_rate;
+        self.metrics.slow_call_rate = slow_call_rate;
+
+        match self.state {
+            CircuitState::Closed if self.window.len() >= self.config.minimum_calls && self.exceeds_thresholds() => {
+                Some(self.transition(CircuitState::Open))
+            },
+            CircuitState::HalfOpen if self.window.len() >= self.config.half_open_calls => {
+                if self.exceeds_thresholds() {
+                    Some(self.transition(CircuitState::Open))
+                } else {
+                    Some(self.transition(CircuitState::Closed))
+                }
+            },
+            _ => None,
+        }
+    }
+
+}
+
+
+type TransitionHook = Box<dyn Fn(&Transition) + Send + Sync>;
+
+/// fails calls fast while the wrapped invoker is degraded
+///
+/// state is kept per method, as told by the [`Method`] entry of the context. wrap each
+/// endpoint invoker of a cluster to get a breaker per endpoint and method
+pub struct CircuitBreaker<I> {
+    inner: I,
+    config: CircuitBreakerConfig,
+    method_configs: HashMap<&'static str, CircuitBreakerConfig>,
+    breakers: Mutex<HashMap<&'static str, Breaker>>,
+    hooks: Vec<TransitionHook>,
+}
+
+impl<I> CircuitBreaker<I> {
+
+    /// `config` applies to every method without a config of its own
+    pub fn new(inner: I, config: CircuitBreakerConfig) -> Self {
+        Self {
+            inner,
+            config,
+            method_configs: HashMap::new(),
+            breakers: Mutex::new(HashMap::new()),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// config for the method with the given `MethodDef::NAME`
+    pub fn with_method_config(mut self, method: &'static str, config: CircuitBreakerConfig) -> Self {
+        self.method_configs.insert(method, config);
+        self
+    }
+
+    /// called on every state change
+    pub fn on_transition(mut self, hook: impl Fn(&Transition) + Send + Sync + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// metrics of the given method, `None` if it was never invoked
+    pub fn metrics(&self, method: &str) -> Option<CircuitMetrics> {
+        self.breakers.lock().unwrap().get(method).map(|b| b.metrics.clone())
+    }
+
+    pub fn state(&self, method: &str) -> CircuitState {
+        self.breakers.lock().unwrap().get(method).map(|b| b.state).unwrap_or(CircuitState::Closed)
+    }
+
+    fn with_breaker<R>(&self, method: &'static str, f: impl FnOnce(&mut Breaker) -> (R, Option<(CircuitState, CircuitState)>)) -> R {
+        let (res, transition) = {
+            let mut breakers = self.breakers.lock().unwrap();
+            let breaker = breakers.entry(method).or_insert_with(|| {
+                Breaker::new(self.method_configs.get(method).unwrap_or(&self.config).clone())
+            });
+            f(breaker)
+        };
+        // hooks run without holding the lock
+        if let Some((from, to)) = transition {
+            let transition = Transition { method, from, to };
+            self.hooks.iter().for_each(|hook| hook(&transition));
+        }
+        res
+    }
+
+}
+
+impl<Req, I, T> Invoker<Req> for CircuitBreaker<I>
+where
+    I: Invoker<Req, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        let method = context.get::<Method>().map(|m| m.name()).unwrap_or_default();
+        if !self.with_breaker(method, |b| b.acquire()) {
+            return Err(InvokerError::CircuitOpen(method));
+        }
+
+        let start = Instant::now();
+        let res = self.inner.invoke(context, req);
+        let slow_call_duration = self.method_configs.get(method).unwrap_or(&self.config).slow_call_duration;
+        let outcome = Outcome { failed: res.is_err(), slow: start.elapsed() >= slow_call_duration };
+        self.with_breaker(method, |b| ((), b.record(outcome)));
+        res
+    }
+}
+
+impl<I> std::fmt::Debug for CircuitBreaker<I> {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CircuitBreaker").field("config", &self.config).finish()
+    }
+}
+
+impl<I: Describe> Describe for CircuitBreaker<I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("circuit_breaker")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{Arc, Mutex}, thread, time::Duration};
+
+    use crate::{context::Method, error::InvokerError, invoker_manager::{InvokeContext, Invoker}};
+    use super::{CircuitBreaker, CircuitBreakerConfig, CircuitState, Transition};
+
+    struct FlakyInvoker;
+
+    impl Invoker<Result<u64, ()>> for FlakyInvoker {
+
+        type Res = Result<u64, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Result<u64, ()>) -> Self::Res {
+            match req {
+                Ok(sleep) => {
+                    thread::sleep(Duration::from_millis(sleep));
+                    Ok(sleep)
+                },
+                Err(_) => Err(anyhow::anyhow!("flaky").into()),
+            }
+        }
+    }
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_rate_threshold: 0.5,
+            slow_call_rate_threshold: 1.0,
+            slow_call_duration: Duration::from_secs(1),
+            window_size: 4,
+            minimum_calls: 4,
+            open_duration: Duration::from_millis(50),
+            half_open_calls: 2,
+        }
+    }
+
+    fn context(method: &'static str) -> InvokeContext {
+        let mut context = InvokeContext::new();
+        context.with_context(Method::named(method));
+        context
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let transitions_clone = transitions.clone();
+        let breaker = CircuitBreaker::new(FlakyInvoker, config())
+            .on_transition(move |t: &Transition| transitions_clone.lock().unwrap().push(t.to));
+        let mut ctx = context("foo");
+
+        for req in [Ok(0), Err(()), Ok(0), Err(())] {
+            let _ = breaker.invoke(&mut ctx, req);
+        }
+        assert_eq!(CircuitState::Open, breaker.state("foo"));
+        assert!(matches!(breaker.invoke(&mut ctx, Ok(0)), Err(InvokerError::CircuitOpen("foo"))));
+        // other methods are not affected
+        assert!(breaker.invoke(&mut context("bar"), Ok(0)).is_ok());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(breaker.invoke(&mut ctx, Ok(0)).is_ok());
+        assert_eq!(CircuitState::HalfOpen, breaker.state("foo"));
+        assert!(breaker.invoke(&mut ctx, Ok(0)).is_ok());
+        assert_eq!(CircuitState::Closed, breaker.state("foo"));
+
+        assert_eq!(vec![CircuitState::Open, CircuitState::HalfOpen, CircuitState::Closed], *transitions.lock().unwrap());
+        let metrics = breaker.metrics("foo").unwrap();
+        assert_eq!((4, 2, 1), (metrics.successful_calls, metrics.failed_calls, metrics.rejected_calls));
+    }
+
+    #[test]
+    fn test_slow_calls_per_method() {
+        let slow = CircuitBreakerConfig { slow_call_rate_threshold: 0.5, slow_call_duration: Duration::from_millis(5), minimum_calls: 2, ..config() };
+        let breaker = CircuitBreaker::new(FlakyInvoker, config()).with_method_config("slow", slow);
+
+        for _ in 0..2 {
+            breaker.invoke(&mut context("slow"), Ok(10)).unwrap();
+            breaker.invoke(&mut context("default"), Ok(10)).unwrap();
+        }
+        assert_eq!(CircuitState::Open, breaker.state("slow"));
+        assert_eq!(CircuitState::Closed, breaker.state("default"));
+        assert_eq!(1.0, breaker.metrics("slow").unwrap().slow_call_rate);
+    }
+
+}
@@ -0,0 +1,299 @@
+use std::{collections::HashMap, sync::{Condvar, Mutex}, time::{Duration, Instant}};
+
+use crate::{context::Method, describe::{Describe, Description}, error::InvokerError, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// additive increase, multiplicative decrease of the limit, driven by call latency
+#[derive(Debug, Clone)]
+pub struct AdaptiveConfig {
+    pub min_limit: usize,
+    pub max_limit: usize,
+    /// calls failing or slower than this shrink the limit, others grow it
+    pub latency_threshold: Duration,
+    /// factor the limit is multiplied by on a slow or failed call, in `0.0..1.0`
+    pub backoff_ratio: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyConfig {
+    /// invocations allowed at the same time, the starting limit in adaptive mode
+    pub max_in_flight: usize,
+    /// invocations allowed to wait for a free slot, `0` rejects right away
+    pub max_queue: usize,
+    /// longest time an invocation waits in the queue before it is rejected
+    pub max_queue_wait: Duration,
+    pub adaptive: Option<AdaptiveConfig>,
+}
+
+impl ConcurrencyConfig {
+
+    pub fn new(max_in_flight: usize) -> Self {
+        Self { max_in_flight, max_queue: 0, max_queue_wait: Duration::ZERO, adaptive: None }
+    }
+
+    pub fn with_queue(mut self, max_queue: usize, max_queue_wait: Duration) -> Self {
+        self.max_queue = max_queue;
+        self.max_queue_wait = max_queue_wait;
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: AdaptiveConfig) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+}
+
+
+struct LimiterState {
+    limit: usize,
+    in_flight: usize,
+    queued: usize,
+}
+
+/// counts in-flight invocations against a limit
+pub struct Limiter {
+    config: ConcurrencyConfig,
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+impl Limiter {
+
+    pub fn new(config: ConcurrencyConfig) -> Self {
+        let state = LimiterState { limit: config.max_in_flight, in_flight: 0, queued: 0 };
+        Self { config, state: Mutex::new(state), released: Condvar::new() }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().unwrap().in_flight
+    }
+
+    /// take a slot, waiting in the queue if allowed
+    pub fn acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight >= state.limit {
+            if state.queued >= self.config.max_queue {
+                return None;
+            }
+            state.queued += 1;
+            let deadline = Instant::now() + self.config.max_queue_wait;
+            while state.in_flight >= state.limit {
+                let now = Instant::now();
+                if now >= deadline {
+                    state.queued -= 1;
+                    return None;
+                }
+                state = self.released.wait_timeout(state, deadline - now).unwrap().0;
+            }
+            state.queued -= 1;
+        }
+        state.in_flight += 1;
+        Some(Permit { limiter: self, start: Instant::now(), failed: false })
+    }
+
+    fn release(&self, latency: Duration, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        if let Some(adaptive) = &self.config.adaptive {
+            if failed || latency > adaptive.latency_threshold {
+                let limit = (state.limit as f64 * adaptive.backoff_ratio) as usize;
+                state.limit = limit.max(adaptive.min_limit);
+            } else if state.limit < adaptive.max_limit {
+                state.limit += 1;
+                self.released.notify_all();
+            }
+        }
+        self.released.notify_one();
+    }
+
+}
+
+
+/// a taken slot, given back on drop
+pub struct Permit<'a> {
+    limiter: &'a Limiter,
+    start: Instant,
+    failed: bool,
+}
+
+impl Drop for Permit<'_> {
+
+    fn drop(&mut self) {
+        self.limiter.release(self.start.elapsed(), self.failed);
+    }
+}
+
+
+/// bulkhead capping the in-flight invocations of an invoker, and optionally of single methods
+///
+/// a call needs a slot of the invoker-wide limiter and of its method's limiter, if the method
+/// has one. calls which can't get a slot fail with [`InvokerError::ConcurrencyLimited`]
+pub struct ConcurrencyLimit<I> {
+    inner: I,
+    limiter: Limiter,
+    method_limiters: HashMap<&'static str, Limiter>,
+}
+
+impl<I> ConcurrencyLimit<I> {
+
+    pub fn new(inner: I, config: ConcurrencyConfig) -> Self {
+        Self { inner, limiter: Limiter::new(config), method_limiters: HashMap::new() }
+    }
+
+    /// limit for the method with the given `MethodDef::NAME`, on top of the invoker-wide limit
+    pub fn with_method_limit(mut self, method: &'static str, config: ConcurrencyConfig) -> Self {
+        self.method_limiters.insert(method, Limiter::new(config));
+        self
+    }
+
+    pub fn limiter(&self) -> &Limiter {
+        &self.limiter
+    }
+
+    pub fn method_limiter(&self, method: &str) -> Option<&Limiter> {
+        self.method_limiters.get(method)
+    }
+
+}
+
+impl<Req, I, T> Invoker<Req> for ConcurrencyLimit<I>
+where
+    I: Invoker<Req, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        let method = context.get::<Method>().map(|m| m.name()).unwrap_or_default();
+        let mut method_permit = match self.method_limiters.get(method) {
+            Some(limiter) => Some(limiter.acquire().ok_or(InvokerError::ConcurrencyLimited(method))?),
+            None => None,
+        };
+        let mut permit = self.limiter.acquire().ok_or(InvokerError::ConcurrencyLimited(method))?;
+
+        let res = self.inner.invoke(context, req);
+        permit.failed = res.is_err();
+        if let Some(p) = method_permit.as_mut() {
+            p.failed = res.is_err();
+        }
+        res
+    }
+}
+
+impl<I: Describe> Describe for ConcurrencyLimit<I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("concurrency_limit")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{mpsc, Arc, Mutex}, thread, time::Duration};
+
+    use crate::{context::Method, error::{ErrorCode, InvokerError}, invoker_manager::{InvokeContext, Invoker}};
+    use super::{AdaptiveConfig, ConcurrencyConfig, ConcurrencyLimit};
+
+    /// blocks until told to go on through the receiver, or sleeps for the given millis
+    struct BlockingInvoker {
+        gate: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Invoker<Option<u64>> for BlockingInvoker {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Option<u64>) -> Self::Res {
+            match req {
+                Some(millis) => thread::sleep(Duration::from_millis(millis)),
+                None => self.gate.lock().unwrap().recv().unwrap(),
+            }
+            Ok(())
+        }
+    }
+
+    fn blocking() -> (BlockingInvoker, mpsc::Sender<()>) {
+        let (tx, rx) = mpsc::channel();
+        (BlockingInvoker { gate: Mutex::new(rx) }, tx)
+    }
+
+    fn wait_in_flight(limit: &ConcurrencyLimit<BlockingInvoker>, n: usize) {
+        while limit.limiter().in_flight() < n {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_limit_and_queue() {
+        let (invoker, gate) = blocking();
+        let config = ConcurrencyConfig::new(1).with_queue(1, Duration::from_secs(5));
+        let limit = Arc::new(ConcurrencyLimit::new(invoker, config));
+
+        let first = { let l = limit.clone(); thread::spawn(move || l.invoke(&mut InvokeContext::new(), None)) };
+        wait_in_flight(&limit, 1);
+        let queued = { let l = limit.clone(); thread::spawn(move || l.invoke(&mut InvokeContext::new(), Some(0))) };
+        while limit.limiter().state.lock().unwrap().queued < 1 {
+            thread::yield_now();
+        }
+
+        // queue is full
+        let rejected = limit.invoke(&mut InvokeContext::new(), Some(0)).unwrap_err();
+        assert_eq!(ErrorCode::ConcurrencyLimited, rejected.code());
+
+        gate.send(()).unwrap();
+        assert!(first.join().unwrap().is_ok());
+        assert!(queued.join().unwrap().is_ok());
+        assert_eq!(0, limit.limiter().in_flight());
+    }
+
+    #[test]
+    fn test_queue_wait_and_method_limit() {
+        let (invoker, gate) = blocking();
+        let config = ConcurrencyConfig::new(10);
+        let method_config = ConcurrencyConfig::new(1).with_queue(1, Duration::from_millis(20));
+        let limit = Arc::new(ConcurrencyLimit::new(invoker, config).with_method_limit("slow", method_config));
+
+        let first = {
+            let l = limit.clone();
+            thread::spawn(move || {
+                let mut context = InvokeContext::new();
+                context.with_context(Method::named("slow"));
+                l.invoke(&mut context, None)
+            })
+        };
+        wait_in_flight(&limit, 1);
+
+        let mut context = InvokeContext::new();
+        context.with_context(Method::named("slow"));
+        assert!(matches!(limit.invoke(&mut context, Some(0)), Err(InvokerError::ConcurrencyLimited("slow"))));
+        // other methods only share the invoker-wide limit
+        assert!(limit.invoke(&mut InvokeContext::new(), Some(0)).is_ok());
+
+        gate.send(()).unwrap();
+        assert!(first.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_adaptive_limit() {
+        let (invoker, _gate) = blocking();
+        let adaptive = AdaptiveConfig { min_limit: 2, max_limit: 5, latency_threshold: Duration::from_millis(5), backoff_ratio: 0.5 };
+        let limit = ConcurrencyLimit::new(invoker, ConcurrencyConfig::new(4).with_adaptive(adaptive));
+
+        limit.invoke(&mut InvokeContext::new(), Some(0)).unwrap();
+        assert_eq!(5, limit.limiter().limit());
+        limit.invoke(&mut InvokeContext::new(), Some(0)).unwrap();
+        assert_eq!(5, limit.limiter().limit());
+        limit.invoke(&mut InvokeContext::new(), Some(10)).unwrap();
+        assert_eq!(2, limit.limiter().limit());
+        limit.invoke(&mut InvokeContext::new(), Some(10)).unwrap();
+        assert_eq!(2, limit.limiter().limit());
+    }
+
+}
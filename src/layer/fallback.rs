@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use crate::{context::Degraded, describe::{Describe, Description}, error::{ErrorCode, InvokerError}, invoker_manager::{InvokeContext, Invoker}};
+
+
+/// invoker answering every request with the same response
+#[derive(Debug, Clone)]
+pub struct StaticResponse<T> {
+    response: T,
+}
+
+impl<T> StaticResponse<T> {
+
+    pub fn new(response: T) -> Self {
+        Self { response }
+    }
+
+}
+
+impl<Req, T: Clone> Invoker<Req> for StaticResponse<T> {
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, _context: &mut InvokeContext, _req: Req) -> Self::Res {
+        Ok(self.response.clone())
+    }
+}
+
+
+/// routes failed calls to a fallback invoker
+///
+/// the fallback can be any invoker, e.g. a [`StaticResponse`] or a
+/// [`Managed`](crate::invoker_manager::Managed) one registered in an `InvokerManager`.
+/// answers of the fallback leave a [`Degraded`] entry in the context, which is cleared
+/// again when the context is used for the next call. a `Degraded` the context inherits is
+/// shadowed by one without a cause
+pub struct Fallback<I, F> {
+    inner: I,
+    fallback: F,
+    codes: Option<HashSet<ErrorCode>>,
+}
+
+impl<I, F> Fallback<I, F> {
+
+    /// falls back on every error
+    pub fn new(inner: I, fallback: F) -> Self {
+        Self { inner, fallback, codes: None }
+    }
+
+    /// only fall back on errors with one of the given codes
+    pub fn on_codes(mut self, codes: impl IntoIterator<Item = ErrorCode>) -> Self {
+        self.codes = Some(codes.into_iter().collect());
+        self
+    }
+
+    fn triggers(&self, e: &InvokerError) -> bool {
+        self.codes.as_ref().map(|codes| codes.contains(&e.code())).unwrap_or(true)
+    }
+
+}
+
+impl<Req, I, F, T> Invoker<Req> for Fallback<I, F>
+where
+    Req: Clone,
+    I: Invoker<Req, Res = Result<T, InvokerError>>,
+    F: Invoker<Req, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        // left over from an earlier call with the same context
+        context.remove::<Degraded>();
+        if context.contains::<Degraded>() {
+            context.with_context(Degraded { cause: None });
+        }
+        match self.inner.invoke(context, req.clone()) {
+            Err(e) if self.triggers(&e) => {
+                let res = self.fallback.invoke(context, req);
+                if res.is_ok() {
+                    context.with_context(Degraded { cause: Some(e) });
+                }
+                res
+            },
+            res => res,
+        }
+    }
+}
+
+impl<I: Describe, F> Describe for Fallback<I, F> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("fallback")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::Arc;
+
+    use crate::{context::Degraded, error::{ErrorCode, InvokerError}, invoker_manager::{InvokeContext, Invoker, InvokerManager, Managed}, Typed};
+    use super::{Fallback, StaticResponse};
+
+    /// fails unless asked for `ok`
+    struct FailingInvoker;
+
+    impl Invoker<String> for FailingInvoker {
+
+        type Res = Result<String, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: String) -> Self::Res {
+            match req.as_str() {
+                "open" => Err(InvokerError::CircuitOpen("get")),
+                "ok" => Ok(req),
+                _ => Err(anyhow::anyhow!("boom").into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let fallback = Fallback::new(FailingInvoker, StaticResponse::new("cached".to_owned()))
+            .on_codes([ErrorCode::CircuitOpen]);
+
+        let mut context = InvokeContext::new();
+        assert_eq!("cached", fallback.invoke(&mut context, "open".to_owned()).unwrap());
+        assert_eq!(ErrorCode::CircuitOpen, context.get::<Degraded>().unwrap().cause.as_ref().unwrap().code());
+
+        // the primary answers again
+        assert_eq!("ok", fallback.invoke(&mut context, "ok".to_owned()).unwrap());
+        assert!(context.get::<Degraded>().is_none());
+
+        let mut context = InvokeContext::new();
+        assert!(fallback.invoke(&mut context, "other".to_owned()).is_err());
+        assert!(context.get::<Degraded>().is_none());
+    }
+
+    #[test]
+    fn test_inherited_degraded() {
+        let fallback = Fallback::new(FailingInvoker, StaticResponse::new("cached".to_owned()));
+        let mut parent = InvokeContext::new();
+        assert_eq!("cached", fallback.invoke(&mut parent, "other".to_owned()).unwrap());
+        let parent = Arc::new(parent);
+
+        let mut context = InvokeContext::with_parent(parent.clone());
+        assert_eq!("ok", fallback.invoke(&mut context, "ok".to_owned()).unwrap());
+        assert!(!context.get::<Degraded>().unwrap().is_degraded());
+        assert!(parent.get::<Degraded>().unwrap().is_degraded());
+
+        let mut context = InvokeContext::with_parent(parent);
+        assert_eq!("cached", fallback.invoke(&mut context, "other".to_owned()).unwrap());
+        assert!(context.get::<Degraded>().unwrap().is_degraded());
+    }
+
+    #[test]
+    fn test_managed_fallback() {
+        let mut manager = InvokerManager::new();
+        manager.add_invoker::<String, _>(StaticResponse::new("managed".to_owned()));
+        let manager = Arc::new(manager);
+        let fallback = Fallback::new(FailingInvoker, Managed::<StaticResponse<String>>::new(manager));
+
+        let mut context = InvokeContext::new();
+        assert_eq!("managed", fallback.invoke(&mut context, "other".to_owned()).unwrap());
+        assert!(context.get::<Degraded>().is_some());
+
+        let missing = Fallback::new(FailingInvoker, Managed::<StaticResponse<String>>::new(Arc::new(InvokerManager::new())));
+        let err = missing.invoke(&mut InvokeContext::new(), "other".to_owned()).unwrap_err();
+        assert_eq!(ErrorCode::NotRegistered, err.code());
+    }
+
+}
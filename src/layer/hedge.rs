@@ -0,0 +1,278 @@
+use std::{collections::VecDeque, future::Future, pin::Pin, sync::{Arc, Mutex}, task::Poll, time::{Duration, Instant}};
+
+use futures_util::{future::poll_fn, stream::FuturesUnordered, StreamExt};
+
+use crate::{context::Deadline, describe::{Describe, Description}, error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}, timer::Delay, Typed};
+
+
+#[derive(Debug, Clone)]
+pub struct HedgeConfig {
+    /// latency percentile of recent calls after which one more attempt is sent, in `0.0..=1.0`
+    pub percentile: f64,
+    /// lower bound of the hedge delay, also used until `min_samples` latencies are recorded
+    pub min_delay: Duration,
+    /// attempts per call, including the first one
+    pub max_attempts: usize,
+    /// number of recent latencies the percentile is computed over
+    pub window_size: usize,
+    pub min_samples: usize,
+}
+
+impl Default for HedgeConfig {
+
+    fn default() -> Self {
+        Self {
+            percentile: 0.95,
+            min_delay: Duration::from_millis(10),
+            max_attempts: 2,
+            window_size: 1000,
+            min_samples: 20,
+        }
+    }
+}
+
+
+/// sends a duplicate of a slow call, the first successful answer wins and the other attempts are dropped
+///
+/// only methods whose `MethodDefInfo` marks them idempotent are hedged. the first attempt is
+/// invoked with the caller's context right away, every further one only once its hedge delay
+/// is up, or the attempts before it failed, with a child of a
+/// [snapshot](InvokeContext::snapshot) taken before the first. later attempts only see the
+/// entries of the context which can be cloned, entries they leave in their child are not
+/// seen by the caller. no attempt is started after the [`Deadline`] of the context, and the
+/// call fails with [`InvokerError::DeadlineExceeded`] once it passes
+pub struct Hedge<M, I> {
+    method: M,
+    inner: Arc<I>,
+    config: HedgeConfig,
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+}
+
+impl<M: MethodDef, I> Hedge<M, I> {
+
+    pub fn new(method: M, inner: I, config: HedgeConfig) -> Self {
+        Self { method, inner: Arc::new(inner), config, latencies: Arc::new(Mutex::new(VecDeque::new())) }
+    }
+
+    /// current delay before sending another attempt
+    pub fn delay(&self) -> Duration {
+        let latencies = self.latencies.lock().unwrap();
+        if latencies.len() < self.config.min_samples.max(1) {
+            return self.config.min_delay;
+        }
+        let mut sorted = latencies.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        let i = ((sorted.len() as f64 * self.config.percentile).ceil() as usize).clamp(1, sorted.len()) - 1;
+        sorted[i].max(self.config.min_delay)
+    }
+
+}
+
+// attempts answer along with the instant they were started at
+async fn timed<F: Future>(attempt: F, started: Instant) -> (F::Output, Instant) {
+    (attempt.await, started)
+}
+
+impl<M, I, F, T> Invoker<M::Request> for Hedge<M, I>
+where
+    M: MethodDef,
+    M::Request: Clone,
+    I: Invoker<M::Request, Res = F> + Send + Sync + 'static,
+    F: Future<Output = Result<T, InvokerError>> + Send + 'static,
+    T: Send + 'static
+{
+
+    type Res = InvokerFuture<T>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        if self.config.max_attempts <= 1 || !self.method.get_method_def_info().is_idempotent() {
+            return InvokerFuture::new(self.inner.invoke(context, req));
+        }
+
+        let start = Instant::now();
+        let delay = self.delay();
+        let deadline = context.get::<Deadline>().copied();
+        let base = Arc::new(context.snapshot());
+        let mut running = FuturesUnordered::new();
+        running.push(timed(self.inner.invoke(context, req.clone()), start));
+
+        let inner = self.inner.clone();
+        let max_attempts = self.config.max_attempts;
+        let mut attempts = 1;
+        // invoke one more attempt if there is any left
+        let mut start_attempt = move |running: &mut FuturesUnordered<_>| {
+            let now = Instant::now();
+            if attempts >= max_attempts || deadline.map(|d| now >= d.0).unwrap_or(false) {
+                return false;
+            }
+            attempts += 1;
+            running.push(timed(inner.invoke(&mut InvokeContext::with_parent(base.clone()), req.clone()), now));
+            true
+        };
+
+        let latencies = self.latencies.clone();
+        let window_size = self.config.window_size;
+        let mut next = Delay::until(start + delay);
+        let mut deadline = deadline.map(|d| Delay::until(d.0));
+        let mut last_err = None;
+        let mut exhausted = false;
+
+        let fut = poll_fn(move |cx| {
+            if let Some(deadline) = deadline.as_mut() {
+                if Pin::new(deadline).poll(cx).is_ready() {
+                    running.clear();
+                    return Poll::Ready(Err(InvokerError::DeadlineExceeded));
+                }
+            }
+            loop {
+                match running.poll_next_unpin(cx) {
+                    Poll::Ready(Some((Ok(res), started))) => {
+                        let mut latencies = latencies.lock().unwrap();
+                        latencies.push_back(Instant::now().saturating_duration_since(started));
+                        while latencies.len() > window_size {
+                            latencies.pop_front();
+                        }
+                        // drop the attempts still running
+                        running.clear();
+                        return Poll::Ready(Ok(res));
+                    },
+                    Poll::Ready(Some((Err(e), _))) => {
+                        last_err = Some(e);
+                        // no point in waiting for the delay after a failure
+                        if !exhausted && start_attempt(&mut running) {
+                            next = Delay::until(Instant::now() + delay);
+                        } else {
+                            exhausted = true;
+                        }
+                        continue;
+                    },
+                    Poll::Ready(None) => {
+                        return Poll::Ready(Err(last_err.take().unwrap_or(InvokerError::DeadlineExceeded)));
+                    },
+                    Poll::Pending => {},
+                }
+                if !exhausted && Pin::new(&mut next).poll(cx).is_ready() {
+                    if start_attempt(&mut running) {
+                        next = Delay::until(Instant::now() + delay);
+                        continue;
+                    }
+                    // only the running attempts are left to wait for
+                    exhausted = true;
+                }
+                return Poll::Pending;
+            }
+        });
+        InvokerFuture::new(fut)
+    }
+}
+
+impl<M, I: Describe> Describe for Hedge<M, I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("hedge")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+    use futures_executor::block_on;
+    use serde_json::Value;
+
+    use crate::{context::Deadline, error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker}, layer::test_method::GetMethod, timer::Delay};
+    use super::{Hedge, HedgeConfig};
+
+    fn method(idempotent: bool) -> GetMethod {
+        GetMethod::new(MethodDefInfo { idempotent, ..MethodDefInfo::default() })
+    }
+
+    /// the n-th invoke answers with n after `latencies[n]` millis, like endpoints picked round robin
+    struct SlowInvoker {
+        calls: AtomicUsize,
+        latencies: Vec<u64>,
+        token: Arc<()>,
+    }
+
+    impl SlowInvoker {
+        fn new(latencies: Vec<u64>) -> Self {
+            Self { calls: AtomicUsize::new(0), latencies, token: Arc::new(()) }
+        }
+    }
+
+    impl Invoker<Value> for SlowInvoker {
+
+        type Res = InvokerFuture<usize>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: Value) -> Self::Res {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            let latency = Duration::from_millis(self.latencies[n % self.latencies.len()]);
+            let token = self.token.clone();
+            InvokerFuture::new(async move {
+                Delay::until(Instant::now() + latency).await;
+                drop(token);
+                Ok(n)
+            })
+        }
+    }
+
+    fn config() -> HedgeConfig {
+        HedgeConfig { min_delay: Duration::from_millis(20), ..HedgeConfig::default() }
+    }
+
+    #[test]
+    fn test_hedge() {
+        let hedge = Hedge::new(method(true), SlowInvoker::new(vec![500, 5]), config());
+        let start = Instant::now();
+        let fut = hedge.invoke(&mut InvokeContext::new(), Value::Null);
+        assert_eq!(1, block_on(fut).unwrap());
+        assert!(start.elapsed() < Duration::from_millis(400));
+        // the slow attempt got cancelled
+        assert_eq!(1, Arc::strong_count(&hedge.inner.token));
+    }
+
+    #[test]
+    fn test_context_left_to_caller() {
+        struct Token;
+        let hedge = Hedge::new(method(true), SlowInvoker::new(vec![500, 5]), config());
+        let mut context = InvokeContext::new();
+        context.with_context(Token);
+        context.insert_cloneable(Deadline::after(Duration::from_secs(10)));
+        assert_eq!(1, block_on(hedge.invoke(&mut context, Value::Null)).unwrap());
+        // entries stay in the caller's context itself
+        assert!(context.parent().is_none());
+        assert!(context.take::<Token>().is_some());
+        assert!(context.take::<Deadline>().is_some());
+    }
+
+    #[test]
+    fn test_fast_first_attempt() {
+        let hedge = Hedge::new(method(true), SlowInvoker::new(vec![1]), HedgeConfig { max_attempts: 3, ..config() });
+        assert_eq!(0, block_on(hedge.invoke(&mut InvokeContext::new(), Value::Null)).unwrap());
+        // the duplicates were never invoked
+        assert_eq!(1, hedge.inner.calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_not_idempotent() {
+        let hedge = Hedge::new(method(false), SlowInvoker::new(vec![40, 5]), config());
+        assert_eq!(0, block_on(hedge.invoke(&mut InvokeContext::new(), Value::Null)).unwrap());
+        assert_eq!(1, hedge.inner.calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let hedge = Hedge::new(method(true), SlowInvoker::new(vec![500]), HedgeConfig { max_attempts: 3, ..config() });
+        let mut context = InvokeContext::new();
+        context.with_context(Deadline::after(Duration::from_millis(30)));
+        let start = Instant::now();
+        let res = block_on(hedge.invoke(&mut context, Value::Null));
+        assert!(matches!(res, Err(InvokerError::DeadlineExceeded)));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        // second attempt starts at 20ms, a third one would start after the deadline
+        assert_eq!(2, hedge.inner.calls.load(Ordering::SeqCst));
+    }
+
+}
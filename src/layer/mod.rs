@@ -0,0 +1,48 @@
+//! layers wrap an [`Invoker`](crate::invoker_manager::Invoker) and are invokers themselves,
+//! so they stack in any order
+
+pub mod circuit_breaker;
+pub mod concurrency;
+pub mod rate_limit;
+pub mod hedge;
+pub mod fallback;
+pub mod cache;
+pub mod single_flight;
+pub mod batch;
+pub mod retry;
+pub mod timeout;
+pub mod auth;
+
+
+/// the method invoked by the layer tests, `get` of json values with the info a test needs
+#[cfg(test)]
+pub(crate) mod test_method {
+
+    use serde_json::Value;
+
+    use crate::invoker::invoker5::{MethodDef, MethodDefInfo};
+
+    pub(crate) struct GetMethod {
+        pub(crate) info: MethodDefInfo,
+    }
+
+    impl GetMethod {
+        pub(crate) fn new(info: MethodDefInfo) -> Self {
+            Self { info }
+        }
+    }
+
+    impl MethodDef for GetMethod {
+
+        const NAME: &'static str = "get";
+
+        type Request = Value;
+
+        type Response = Value;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, sync::Mutex, thread, time::{Duration, Instant}};
+
+use crate::{context::Metadata, describe::{Describe, Description}, error::InvokerError, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// what to do with a call finding its bucket empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitMode {
+    /// fail with [`InvokerError::RateLimited`] right away
+    Reject,
+    /// wait for a token, calls which would wait longer than the given duration are rejected
+    Block(Duration),
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// tokens added to each bucket per second
+    pub rate: f64,
+    /// size of each bucket, the number of calls allowed in a burst
+    pub burst: u32,
+    pub mode: RateLimitMode,
+}
+
+impl RateLimitConfig {
+
+    pub fn validate(&self) -> Result<(), RateLimitError> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            return Err(RateLimitError::InvalidRate(self.rate));
+        }
+        if self.burst == 0 {
+            return Err(RateLimitError::ZeroBurst);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RateLimitError {
+    #[error("rate must be a finite number of tokens per second above zero, got {0}")]
+    InvalidRate(f64),
+    #[error("burst must allow at least one call")]
+    ZeroBurst,
+}
+
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// buckets are swept once there are this many of them, and again whenever their
+// number doubled since
+const MIN_SWEEP: usize = 64;
+
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    sweep_at: usize,
+}
+
+type KeyFn = Box<dyn Fn(&InvokeContext) -> Option<String> + Send + Sync>;
+
+/// token bucket rate limit, one bucket per key taken from the context
+///
+/// calls without a key share one bucket. buckets which refilled completely are dropped now
+/// and then, a new one starts full just the same, so callers making up keys don't pile up
+/// buckets. the layer only looks at the context, so it limits inbound calls just as well
+/// when wrapping a server side handler
+pub struct RateLimit<I> {
+    inner: I,
+    config: RateLimitConfig,
+    key: KeyFn,
+    buckets: Mutex<Buckets>,
+}
+
+impl<I> RateLimit<I> {
+
+    /// key calls by a typed entry of the context, e.g. a tenant
+    pub fn by_context<T, F>(inner: I, config: RateLimitConfig, key: F) -> Result<Self, RateLimitError>
+    where
+        T: 'static,
+        F: Fn(&T) -> String + Send + Sync + 'static
+    {
+        Self::new(inner, config, Box::new(move |context| context.get::<T>().map(&key)))
+    }
+
+    /// key calls by the value of a [`Metadata`] entry, e.g. an api key
+    pub fn by_metadata(inner: I, config: RateLimitConfig, name: impl Into<String>) -> Result<Self, RateLimitError> {
+        let name = name.into();
+        Self::new(inner, config, Box::new(move |context| {
+            context.get::<Metadata>().and_then(|m| m.get(&name)).map(|v| v.to_owned())
+        }))
+    }
+
+    fn new(inner: I, config: RateLimitConfig, key: KeyFn) -> Result<Self, RateLimitError> {
+        config.validate()?;
+        Ok(Self { inner, config, key, buckets: Mutex::new(Buckets { map: HashMap::new(), sweep_at: MIN_SWEEP }) })
+    }
+
+    /// take a token for `key` at `now`, returns how long the caller has to wait before going on
+    fn acquire(&self, key: &str, now: Instant) -> Option<Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.map.len() >= buckets.sweep_at && !buckets.map.contains_key(key) {
+            let (rate, burst) = (self.config.rate, self.config.burst as f64);
+            buckets.map.retain(|_, b| b.tokens + now.duration_since(b.refilled_at).as_secs_f64() * rate < burst);
+            buckets.sweep_at = MIN_SWEEP.max(buckets.map.len() * 2);
+        }
+        let bucket = buckets.map.entry(key.to_owned()).or_insert_with(|| {
+            Bucket { tokens: self.config.burst as f64, refilled_at: now }
+        });
+        let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.config.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.config.burst as f64);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Some(Duration::ZERO);
+        }
+        match self.config.mode {
+            RateLimitMode::Reject => None,
+            RateLimitMode::Block(max_wait) => {
+                let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.config.rate);
+                if wait > max_wait {
+                    return None;
+                }
+                // reserve the token now, so waiters are served in order
+                bucket.tokens -= 1.0;
+                Some(wait)
+            },
+        }
+    }
+
+}
+
+impl<Req, I, T> Invoker<Req> for RateLimit<I>
+where
+    I: Invoker<Req, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Req) -> Self::Res {
+        let key = (self.key)(context).unwrap_or_default();
+        match self.acquire(&key, Instant::now()) {
+            Some(wait) => {
+                if !wait.is_zero() {
+                    thread::sleep(wait);
+                }
+                self.inner.invoke(context, req)
+            },
+            None => Err(InvokerError::RateLimited(key)),
+        }
+    }
+}
+
+impl<I: Describe> Describe for RateLimit<I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("rate_limit")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::time::{Duration, Instant};
+
+    use crate::{context::Metadata, error::InvokerError, invoker_manager::{InvokeContext, Invoker}};
+    use super::{RateLimit, RateLimitConfig, RateLimitError, RateLimitMode};
+
+    struct OkInvoker;
+
+    impl Invoker<()> for OkInvoker {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: ()) -> Self::Res {
+            Ok(())
+        }
+    }
+
+    struct Tenant(&'static str);
+
+    fn tenant(name: &'static str) -> InvokeContext {
+        let mut context = InvokeContext::new();
+        context.with_context(Tenant(name));
+        context
+    }
+
+    #[test]
+    fn test_reject_by_context() {
+        let config = RateLimitConfig { rate: 0.001, burst: 2, mode: RateLimitMode::Reject };
+        let limit = RateLimit::by_context(OkInvoker, config, |t: &Tenant| t.0.to_owned()).unwrap();
+
+        assert!(limit.invoke(&mut tenant("a"), ()).is_ok());
+        assert!(limit.invoke(&mut tenant("a"), ()).is_ok());
+        let err = limit.invoke(&mut tenant("a"), ()).unwrap_err();
+        assert!(matches!(err, InvokerError::RateLimited(key) if key == "a"));
+        // every tenant has its own bucket
+        assert!(limit.invoke(&mut tenant("b"), ()).is_ok());
+    }
+
+    #[test]
+    fn test_block_by_metadata() {
+        let config = RateLimitConfig { rate: 100.0, burst: 1, mode: RateLimitMode::Block(Duration::from_millis(15)) };
+        let limit = RateLimit::by_metadata(OkInvoker, config, "api-key").unwrap();
+        let mut context = InvokeContext::new();
+        let mut metadata = Metadata::new();
+        metadata.insert("api-key", "k1");
+        context.with_context(metadata);
+
+        let start = Instant::now();
+        assert!(limit.invoke(&mut context, ()).is_ok());
+        assert!(limit.invoke(&mut context, ()).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(9));
+        // reserve the next token, the call after it would wait too long
+        assert!(limit.acquire("k1", Instant::now()).is_some());
+        assert!(matches!(limit.invoke(&mut context, ()), Err(InvokerError::RateLimited(_))));
+    }
+
+    #[test]
+    fn test_evict_full_buckets() {
+        let config = RateLimitConfig { rate: 50.0, burst: 1, mode: RateLimitMode::Reject };
+        let limit = RateLimit::by_metadata(OkInvoker, config, "api-key").unwrap();
+        let start = Instant::now();
+        for i in 0..super::MIN_SWEEP * 2 {
+            assert!(limit.acquire(&format!("key-{}", i), start).is_some());
+        }
+        assert_eq!(super::MIN_SWEEP * 2, limit.buckets.lock().unwrap().map.len());
+
+        // every bucket refilled a second later
+        assert!(limit.acquire("last", start + Duration::from_secs(1)).is_some());
+        assert_eq!(1, limit.buckets.lock().unwrap().map.len());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let config = RateLimitConfig { rate, burst: 1, mode: RateLimitMode::Block(Duration::from_secs(1)) };
+            let err = RateLimit::by_metadata(OkInvoker, config, "api-key").err().unwrap();
+            assert!(matches!(err, RateLimitError::InvalidRate(_)));
+        }
+        let config = RateLimitConfig { rate: 1.0, burst: 0, mode: RateLimitMode::Reject };
+        assert!(matches!(RateLimit::by_metadata(OkInvoker, config, "api-key"), Err(RateLimitError::ZeroBurst)));
+    }
+
+}
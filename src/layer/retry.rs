@@ -0,0 +1,151 @@
+use std::{thread, time::Instant};
+
+use crate::{context::Deadline, describe::{Describe, Description}, error::{ErrorCode, InvokerError}, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}, Typed};
+
+
+/// retries failed calls as the `RetryPolicy` in the `MethodDefInfo` of `M` says
+///
+/// calls which were never sent, like those without an endpoint, are always retried, others
+/// only if the method is idempotent. the backoff before a retry blocks the calling thread,
+/// and no retry is made which could not start before the [`Deadline`] of the context.
+/// methods without a policy are invoked once
+pub struct Retry<M, I> {
+    method: M,
+    inner: I,
+}
+
+impl<M: MethodDef, I> Retry<M, I> {
+
+    pub fn new(method: M, inner: I) -> Self {
+        Self { method, inner }
+    }
+
+    fn retryable(&self, e: &InvokerError) -> bool {
+        match e.code() {
+            ErrorCode::Unavailable => true,
+            ErrorCode::General => self.method.get_method_def_info().idempotent,
+            _ => false,
+        }
+    }
+
+}
+
+impl<M, I, T> Invoker<M::Request> for Retry<M, I>
+where
+    M: MethodDef,
+    M::Request: Clone,
+    I: Invoker<M::Request, Res = Result<T, InvokerError>>
+{
+
+    type Res = Result<T, InvokerError>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        let policy = match &self.method.get_method_def_info().retry {
+            Some(policy) if policy.max_attempts > 1 => policy,
+            _ => return self.inner.invoke(context, req),
+        };
+
+        let mut retry = 0;
+        loop {
+            let e = match self.inner.invoke(context, req.clone()) {
+                Ok(res) => return Ok(res),
+                Err(e) => e,
+            };
+            retry += 1;
+            if retry >= policy.max_attempts || !self.retryable(&e) {
+                return Err(e);
+            }
+            let starts_at = Instant::now() + policy.backoff(retry);
+            if context.get::<Deadline>().map(|d| starts_at >= d.0).unwrap_or(false) {
+                return Err(e);
+            }
+            thread::sleep(starts_at.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+impl<M, I: Describe> Describe for Retry<M, I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("retry")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+    use serde_json::Value;
+
+    use crate::{context::Deadline, error::InvokerError, invoker::invoker5::{MethodDefInfo, RetryPolicy}, invoker_manager::{InvokeContext, Invoker}, layer::test_method::GetMethod};
+    use super::Retry;
+
+    fn method(idempotent: bool) -> GetMethod {
+        let retry = RetryPolicy { max_attempts: 3, backoff: Duration::from_millis(10), ..RetryPolicy::default() };
+        GetMethod::new(MethodDefInfo { idempotent, retry: Some(retry), ..MethodDefInfo::default() })
+    }
+
+    /// fails with the given errors first, then answers with the number of calls
+    struct Flaky {
+        calls: AtomicUsize,
+        errors: Vec<InvokerError>,
+    }
+
+    impl Flaky {
+        fn new(errors: Vec<InvokerError>) -> Self {
+            Self { calls: AtomicUsize::new(0), errors }
+        }
+    }
+
+    impl Invoker<Value> for Flaky {
+
+        type Res = Result<usize, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: Value) -> Self::Res {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.errors.get(n) {
+                Some(e) => Err(e.clone()),
+                None => Ok(n + 1),
+            }
+        }
+    }
+
+    fn general() -> InvokerError {
+        anyhow::anyhow!("reset by peer").into()
+    }
+
+    #[test]
+    fn test_retry() {
+        let retry = Retry::new(method(true), Flaky::new(vec![general(), InvokerError::NoEndpoint]));
+        assert_eq!(3, retry.invoke(&mut InvokeContext::new(), Value::Null).unwrap());
+
+        let retry = Retry::new(method(true), Flaky::new(vec![general(); 3]));
+        assert!(retry.invoke(&mut InvokeContext::new(), Value::Null).is_err());
+        assert_eq!(3, retry.inner.calls.load(Ordering::SeqCst));
+
+        // the request may have been processed already
+        let retry = Retry::new(method(false), Flaky::new(vec![general()]));
+        assert!(retry.invoke(&mut InvokeContext::new(), Value::Null).is_err());
+        let retry = Retry::new(method(false), Flaky::new(vec![InvokerError::NoEndpoint]));
+        assert_eq!(2, retry.invoke(&mut InvokeContext::new(), Value::Null).unwrap());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let retry = Retry::new(method(true), Flaky::new(vec![general(); 3]));
+        let mut context = InvokeContext::new();
+        context.with_context(Deadline::after(Duration::from_millis(15)));
+        assert!(retry.invoke(&mut context, Value::Null).is_err());
+        // the second retry would start 30ms in
+        assert_eq!(2, retry.inner.calls.load(Ordering::SeqCst));
+
+        let mut get = method(true);
+        get.info.retry = None;
+        let retry = Retry::new(get, Flaky::new(vec![general()]));
+        assert!(retry.invoke(&mut InvokeContext::new(), Value::Null).is_err());
+        assert_eq!(1, retry.inner.calls.load(Ordering::SeqCst));
+    }
+
+}
@@ -0,0 +1,169 @@
+use std::{collections::HashMap, future::Future, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+
+use futures_util::{future::{BoxFuture, Shared, WeakShared}, FutureExt};
+
+use crate::{describe::{Describe, Description}, error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}};
+use super::cache::{EncodedKey, RequestKey};
+
+
+type Flight<T> = Shared<BoxFuture<'static, Result<T, InvokerError>>>;
+
+type Flights<T> = Arc<Mutex<HashMap<RequestKey, (u64, WeakShared<BoxFuture<'static, Result<T, InvokerError>>>)>>>;
+
+/// coalesces concurrent calls of the same idempotent request into one invocation
+///
+/// calls are keyed like the [`Cache`](super::cache::Cache) does. every caller gets a clone
+/// of the result, errors included. the invocation keeps going as long as one caller still
+/// waits for it, so dropping the caller which started it cancels nothing, and it is only
+/// dropped once every caller is gone
+pub struct SingleFlight<M, I, T> {
+    method: M,
+    inner: I,
+    flights: Flights<T>,
+    next_id: AtomicU64,
+}
+
+impl<M: MethodDef, I, T> SingleFlight<M, I, T> {
+
+    pub fn new(method: M, inner: I) -> Self {
+        Self { method, inner, flights: Arc::new(Mutex::new(HashMap::new())), next_id: AtomicU64::new(0) }
+    }
+
+    /// number of invocations in flight
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().values().filter(|(_, f)| f.upgrade().is_some()).count()
+    }
+
+}
+
+impl<M, I, F, T> Invoker<M::Request> for SingleFlight<M, I, T>
+where
+    M: MethodDef,
+    M::Request: EncodedKey,
+    I: Invoker<M::Request, Res = F>,
+    F: Future<Output = Result<T, InvokerError>> + Send + 'static,
+    T: Clone + Send + Sync + 'static
+{
+
+    type Res = InvokerFuture<T>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        let key = match RequestKey::of::<M>(&req) {
+            Some(key) if self.method.get_method_def_info().is_idempotent() => key,
+            _ => return InvokerFuture::new(self.inner.invoke(context, req)),
+        };
+
+        let mut flights = self.flights.lock().unwrap();
+        if let Some(flight) = flights.get(&key).and_then(|(_, f)| f.upgrade()) {
+            return InvokerFuture::new(flight);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let fut = self.inner.invoke(context, req);
+        let flights_clone = self.flights.clone();
+        let flight: Flight<T> = async move {
+            let res = fut.await;
+            // later calls start a new flight instead of getting this result
+            let mut flights = flights_clone.lock().unwrap();
+            if flights.get(&key).map(|(i, _)| *i == id).unwrap_or(false) {
+                flights.remove(&key);
+            }
+            res
+        }.boxed().shared();
+        // flights whose callers all went away never got to remove themselves
+        flights.retain(|_, (_, f)| f.upgrade().is_some());
+        flights.insert(key, (id, flight.downgrade().unwrap()));
+        InvokerFuture::new(flight)
+    }
+}
+
+impl<M, I: Describe, T> Describe for SingleFlight<M, I, T> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("single_flight")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::atomic::{AtomicUsize, Ordering}, task::Context, time::{Duration, Instant}};
+
+    use futures_executor::block_on;
+    use futures_util::{future::join_all, task::noop_waker, FutureExt};
+    use serde_json::{json, Value};
+
+    use crate::{error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker}, layer::test_method::GetMethod, timer::Delay};
+    use super::SingleFlight;
+
+    fn single_flight() -> SingleFlight<GetMethod, CountingInvoker, usize> {
+        let info = MethodDefInfo { idempotent: true, ..MethodDefInfo::default() };
+        SingleFlight::new(GetMethod::new(info), CountingInvoker::default())
+    }
+
+    /// answers with the number of calls so far after 20 millis, fails for `"fail"`
+    #[derive(Default)]
+    struct CountingInvoker {
+        calls: AtomicUsize,
+    }
+
+    impl Invoker<Value> for CountingInvoker {
+
+        type Res = InvokerFuture<usize>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Value) -> Self::Res {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            InvokerFuture::new(async move {
+                Delay::until(Instant::now() + Duration::from_millis(20)).await;
+                match req == json!("fail") {
+                    true => Err(anyhow::anyhow!("failed").into()),
+                    false => Ok(n),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let single_flight = single_flight();
+        let mut futs = (0..5).map(|_| single_flight.invoke(&mut InvokeContext::new(), json!(1))).collect::<Vec<_>>();
+        futs.push(single_flight.invoke(&mut InvokeContext::new(), json!(2)));
+        assert_eq!(2, single_flight.in_flight());
+
+        let res = block_on(join_all(futs)).into_iter().map(|r| r.unwrap()).collect::<Vec<_>>();
+        assert_eq!(vec![1, 1, 1, 1, 1, 2], res);
+        assert_eq!(0, single_flight.in_flight());
+
+        // finished flights are not reused
+        assert_eq!(3, block_on(single_flight.invoke(&mut InvokeContext::new(), json!(1))).unwrap());
+    }
+
+    #[test]
+    fn test_error_fan_out() {
+        let single_flight = single_flight();
+        let futs = (0..2).map(|_| single_flight.invoke(&mut InvokeContext::new(), json!("fail"))).collect::<Vec<_>>();
+        for res in block_on(join_all(futs)) {
+            assert!(matches!(res, Err(InvokerError::GeneralError(_))));
+        }
+        assert_eq!(1, single_flight.inner.calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_leader_dropped() {
+        let single_flight = single_flight();
+        let mut leader = single_flight.invoke(&mut InvokeContext::new(), json!(1));
+        let follower = single_flight.invoke(&mut InvokeContext::new(), json!(1));
+        let waker = noop_waker();
+        assert!(leader.poll_unpin(&mut Context::from_waker(&waker)).is_pending());
+        drop(leader);
+        assert_eq!(1, block_on(follower).unwrap());
+
+        // once every caller is gone the flight is dropped
+        let only = single_flight.invoke(&mut InvokeContext::new(), json!(2));
+        drop(only);
+        assert_eq!(0, single_flight.in_flight());
+        assert_eq!(3, block_on(single_flight.invoke(&mut InvokeContext::new(), json!(2))).unwrap());
+    }
+
+}
@@ -0,0 +1,203 @@
+use futures_util::future::{select, Either};
+
+use crate::{context::Deadline, describe::{Describe, Description}, error::{FromInvokerError, InvokerError}, future::InvokerFuture, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}, timer::Delay, Typed};
+
+
+/// responses [`Timeout`] is able to bound
+pub trait WithDeadline: FromInvokerError {
+
+    /// fail with [`InvokerError::DeadlineExceeded`] once `deadline` passed
+    fn with_deadline(self, deadline: Deadline) -> Self;
+
+}
+
+/// a sync call can't be cut short, its result is dropped if it came too late
+impl<T, E: From<InvokerError>> WithDeadline for Result<T, E> {
+
+    fn with_deadline(self, deadline: Deadline) -> Self {
+        if deadline.is_expired() {
+            return Err(InvokerError::DeadlineExceeded.into());
+        }
+        self
+    }
+}
+
+/// the future is dropped at the deadline
+impl<Res: Send + 'static> WithDeadline for InvokerFuture<Res> {
+
+    fn with_deadline(self, deadline: Deadline) -> Self {
+        InvokerFuture::new(async move {
+            match select(self, Delay::until(deadline.0)).await {
+                Either::Left((res, _)) => res,
+                Either::Right(_) => Err(InvokerError::DeadlineExceeded),
+            }
+        })
+    }
+}
+
+
+/// bounds calls by the `timeout` in the `MethodDefInfo` of `M`
+///
+/// the timeout becomes the [`Deadline`] of the context while the inner invoker is invoked,
+/// unless the caller's deadline is earlier, so the layers below and the transport give up in
+/// time. the caller's deadline is put back afterwards. calls fail with
+/// [`InvokerError::DeadlineExceeded`] once the deadline passed, without being invoked if
+/// that is before the call, see [`WithDeadline`] for the responses of calls running late
+pub struct Timeout<M, I> {
+    method: M,
+    inner: I,
+}
+
+impl<M: MethodDef, I> Timeout<M, I> {
+
+    pub fn new(method: M, inner: I) -> Self {
+        Self { method, inner }
+    }
+
+}
+
+impl<M, I> Invoker<M::Request> for Timeout<M, I>
+where
+    M: MethodDef,
+    I: Invoker<M::Request>,
+    I::Res: WithDeadline
+{
+
+    type Res = I::Res;
+
+    fn invoke(&self, context: &mut InvokeContext, req: M::Request) -> Self::Res {
+        let caller = context.get::<Deadline>().copied();
+        let deadline = match (self.method.get_method_def_info().timeout, caller) {
+            (Some(timeout), Some(caller)) => Some(Deadline::after(timeout).min(caller)),
+            (Some(timeout), None) => Some(Deadline::after(timeout)),
+            (None, caller) => caller,
+        };
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return self.inner.invoke(context, req),
+        };
+        if deadline.is_expired() {
+            return I::Res::from_invoker_error(InvokerError::DeadlineExceeded);
+        }
+        let res = if Some(deadline) != caller {
+            let previous = context.insert_cloneable(deadline);
+            let res = self.inner.invoke(context, req);
+            match previous {
+                Some(previous) => { context.insert_cloneable(previous); },
+                None => { context.remove::<Deadline>(); },
+            }
+            res
+        } else {
+            self.inner.invoke(context, req)
+        };
+        res.with_deadline(deadline)
+    }
+}
+
+impl<M, I: Describe> Describe for Timeout<M, I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("timeout")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{thread, time::{Duration, Instant}};
+
+    use futures_executor::block_on;
+    use serde_json::Value;
+
+    use crate::{context::Deadline, error::InvokerError, future::InvokerFuture, invoker::invoker5::MethodDefInfo, invoker_manager::{InvokeContext, Invoker}, layer::test_method::GetMethod, timer::Delay, Typed};
+    use super::Timeout;
+
+    /// answers with the deadline it was given
+    struct DeadlineInvoker;
+
+    impl Invoker<Value> for DeadlineInvoker {
+
+        type Res = Result<Option<Deadline>, InvokerError>;
+
+        fn invoke(&self, context: &mut InvokeContext, _req: Value) -> Self::Res {
+            Ok(context.get::<Deadline>().copied())
+        }
+    }
+
+    /// blocks for the given millis before answering
+    struct SlowInvoker(u64);
+
+    impl Invoker<Value> for SlowInvoker {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: Value) -> Self::Res {
+            thread::sleep(Duration::from_millis(self.0));
+            Ok(())
+        }
+    }
+
+    /// answers after the given millis without blocking
+    struct SlowAsyncInvoker(u64);
+
+    impl Invoker<Value> for SlowAsyncInvoker {
+
+        type Res = InvokerFuture<()>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: Value) -> Self::Res {
+            let delay = Delay::until(Instant::now() + Duration::from_millis(self.0));
+            InvokerFuture::new(async move {
+                delay.await;
+                Ok(())
+            })
+        }
+    }
+
+    fn timeout<I>(millis: u64, inner: I) -> Timeout<GetMethod, I> {
+        let info = MethodDefInfo { timeout: Some(Duration::from_millis(millis)), ..MethodDefInfo::default() };
+        Timeout::new(GetMethod::new(info), inner)
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = timeout(100, DeadlineInvoker);
+        let mut context = InvokeContext::new();
+        let deadline = timeout.invoke(&mut context, Value::Null).unwrap().unwrap();
+        assert!(deadline.0 <= Instant::now() + Duration::from_millis(100));
+        assert!(context.get::<Deadline>().is_none());
+
+        // the caller's deadline is earlier
+        let caller = Deadline::after(Duration::from_millis(10));
+        context.with_context(caller);
+        assert_eq!(Some(caller), timeout.invoke(&mut context, Value::Null).unwrap());
+
+        // the caller's deadline is later, and put back afterwards
+        let caller = Deadline::after(Duration::from_secs(10));
+        context.with_context(caller);
+        assert!(timeout.invoke(&mut context, Value::Null).unwrap().unwrap() < caller);
+        assert_eq!(Some(&caller), context.get::<Deadline>());
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut context = InvokeContext::new();
+        context.with_context(Deadline(Instant::now()));
+        let res = timeout(100, DeadlineInvoker).invoke(&mut context, Value::Null);
+        assert!(matches!(res, Err(InvokerError::DeadlineExceeded)));
+    }
+
+    #[test]
+    fn test_slow_inner() {
+        let res = timeout(10, SlowInvoker(30)).invoke(&mut InvokeContext::new(), Value::Null);
+        assert!(matches!(res, Err(InvokerError::DeadlineExceeded)));
+        assert!(timeout(100, SlowInvoker(1)).invoke(&mut InvokeContext::new(), Value::Null).is_ok());
+
+        let start = Instant::now();
+        let res = block_on(timeout(20, SlowAsyncInvoker(1000)).invoke(&mut InvokeContext::new(), Value::Null));
+        assert!(matches!(res, Err(InvokerError::DeadlineExceeded)));
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(block_on(timeout(100, SlowAsyncInvoker(1)).invoke(&mut InvokeContext::new(), Value::Null)).is_ok());
+    }
+
+}
@@ -1,7 +1,28 @@
 pub mod invoker_manager;
 pub mod invoker;
+pub mod error;
+pub mod future;
+pub mod timer;
+pub mod registry;
+pub mod cluster;
+pub mod context;
+pub mod layer;
+pub mod provide;
+pub mod handler;
+pub mod lifecycle;
+pub mod config;
+pub mod inject;
+pub mod describe;
+pub mod server;
+pub mod schema;
 
 
+/// values looked up by their type
+///
+/// for an [`InvokeContext`](invoker_manager::InvokeContext) both methods see the entries of
+/// its parents, `get_mut` copies such an entry into the context first and changes the
+/// copy, so a child or fork never changes what its parents see. only entries inserted
+/// through `insert_cloneable` can be copied, `get_mut` is `None` for other inherited ones
 pub trait Typed {
 
     fn get<I: 'static>(&self) -> Option<&I>;
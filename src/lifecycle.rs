@@ -0,0 +1,379 @@
+//! starting, checking and stopping the invokers of an [`InvokerManager`]
+//!
+//! invokers opt in by implementing [`Lifecycle`] and being registered with
+//! [`InvokerManager::manage`]. the manager starts them in dependency order and shuts them
+//! down in reverse, e.g. from the service's SIGTERM handler
+
+use std::{any::{type_name, TypeId}, collections::HashMap, fmt, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread, time::{Duration, Instant}};
+
+use serde::Serialize;
+
+use crate::{error::InvokerError, invoker_manager::InvokerManager, Typed};
+
+
+/// hooks of an invoker driven by the manager, every one defaults to doing nothing
+pub trait Lifecycle: Send + Sync {
+
+    /// set up what `start` needs, called on every managed invoker before any is started
+    fn init(&self) -> Result<(), InvokerError> {
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), InvokerError> {
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        Health::Healthy
+    }
+
+    /// stop taking new calls, in-flight calls may finish until `deadline`
+    fn shutdown(&self, _deadline: Instant) -> Result<(), InvokerError> {
+        Ok(())
+    }
+
+    /// calls still running, the manager waits for them during shutdown
+    fn in_flight(&self) -> usize {
+        0
+    }
+
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum Health {
+    Healthy,
+    /// serving, but not as well as it should
+    Degraded(String),
+    Unhealthy(String),
+}
+
+
+/// counter of running calls for [`Lifecycle::in_flight`]
+#[derive(Debug, Clone, Default)]
+pub struct InFlight {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlight {
+
+    /// count a call until the returned guard is dropped
+    pub fn track(&self) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard { count: self.count.clone() }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+}
+
+pub struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum LifecycleError {
+    #[error("managed invoker `{0}` is not registered")]
+    NotRegistered(&'static str),
+    #[error("`{invoker}` depends on `{dependency}` which is not managed")]
+    MissingDependency { invoker: &'static str, dependency: &'static str },
+    #[error("dependency cycle between {0:?}")]
+    Cycle(Vec<&'static str>),
+    #[error("`{invoker}` failed to {stage}")]
+    Failed { invoker: &'static str, stage: &'static str, #[source] error: InvokerError },
+}
+
+
+pub(crate) struct Managed {
+    id: TypeId,
+    name: &'static str,
+    dependencies: Vec<(TypeId, &'static str)>,
+    lifecycle: fn(&InvokerManager) -> Option<&dyn Lifecycle>,
+}
+
+fn lifecycle_of<I: Lifecycle + 'static>(manager: &InvokerManager) -> Option<&dyn Lifecycle> {
+    manager.get::<I>().map(|i| i as &dyn Lifecycle)
+}
+
+impl fmt::Debug for Managed {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Managed").field("name", &self.name).finish()
+    }
+}
+
+
+/// returned by [`InvokerManager::manage`] to declare dependencies
+pub struct Manage<'a> {
+    managed: &'a mut Managed,
+}
+
+impl<'a> Manage<'a> {
+
+    /// `D` is started before and shut down after this invoker
+    pub fn depends_on<D: Lifecycle + 'static>(self) -> Self {
+        self.managed.dependencies.push((TypeId::of::<D>(), type_name::<D>()));
+        self
+    }
+
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// no managed invoker is unhealthy
+    pub ready: bool,
+    pub invokers: Vec<(&'static str, Health)>,
+}
+
+
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// shut down with every call finished
+    pub drained: Vec<&'static str>,
+    /// still had calls running when the timeout was over
+    pub timed_out: Vec<&'static str>,
+    pub failed: Vec<(&'static str, InvokerError)>,
+}
+
+impl ShutdownReport {
+
+    pub fn is_clean(&self) -> bool {
+        self.timed_out.is_empty() && self.failed.is_empty()
+    }
+
+}
+
+
+impl InvokerManager {
+
+    /// let the manager drive the lifecycle of the registered `I`
+    pub fn manage<I: Lifecycle + 'static>(&mut self) -> Manage<'_> {
+        let id = TypeId::of::<I>();
+        let managed = &mut self.managed;
+        let index = match managed.iter().position(|m| m.id == id) {
+            Some(index) => index,
+            None => {
+                managed.push(Managed { id, name: type_name::<I>(), dependencies: Vec::new(), lifecycle: lifecycle_of::<I> });
+                managed.len() - 1
+            },
+        };
+        Manage { managed: &mut managed[index] }
+    }
+
+    /// init and then start every managed invoker, dependencies first
+    pub fn start(&self) -> Result<(), LifecycleError> {
+        let order = self.start_order()?;
+        for stage in ["init", "start"] {
+            for managed in &order {
+                let lifecycle = self.lifecycle(managed)?;
+                let res = if stage == "init" { lifecycle.init() } else { lifecycle.start() };
+                res.map_err(|error| LifecycleError::Failed { invoker: managed.name, stage, error })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn health(&self) -> HealthReport {
+        let invokers = self.managed.iter().map(|managed| {
+            let health = match (managed.lifecycle)(self) {
+                Some(lifecycle) => lifecycle.health(),
+                None => Health::Unhealthy("not registered".to_owned()),
+            };
+            (managed.name, health)
+        }).collect::<Vec<_>>();
+        let ready = invokers.iter().all(|(_, h)| !matches!(h, Health::Unhealthy(_)));
+        HealthReport { ready, invokers }
+    }
+
+    /// shut every managed invoker down, dependents first, each waiting for its in-flight
+    /// calls as long as the overall `timeout` allows
+    pub fn shutdown(&self, timeout: Duration) -> Result<ShutdownReport, LifecycleError> {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+        for managed in self.start_order()?.into_iter().rev() {
+            let lifecycle = match (managed.lifecycle)(self) {
+                Some(lifecycle) => lifecycle,
+                None => continue,
+            };
+            if let Err(e) = lifecycle.shutdown(deadline) {
+                report.failed.push((managed.name, e));
+                continue;
+            }
+            while lifecycle.in_flight() > 0 && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1).min(deadline.saturating_duration_since(Instant::now())));
+            }
+            match lifecycle.in_flight() {
+                0 => report.drained.push(managed.name),
+                _ => report.timed_out.push(managed.name),
+            }
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn managed_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.managed.iter().map(|m| m.name)
+    }
+
+    fn lifecycle(&self, managed: &Managed) -> Result<&dyn Lifecycle, LifecycleError> {
+        (managed.lifecycle)(self).ok_or(LifecycleError::NotRegistered(managed.name))
+    }
+
+    fn start_order(&self) -> Result<Vec<&Managed>, LifecycleError> {
+        let by_id = self.managed.iter().map(|m| (m.id, m)).collect::<HashMap<_, _>>();
+        let mut remaining = HashMap::new();
+        for managed in &self.managed {
+            for (dependency, name) in &managed.dependencies {
+                if !by_id.contains_key(dependency) {
+                    return Err(LifecycleError::MissingDependency { invoker: managed.name, dependency: name });
+                }
+            }
+            remaining.insert(managed.id, managed.dependencies.len());
+        }
+
+        // kahn's algorithm, keeping the registration order among independent invokers
+        let mut order = Vec::with_capacity(self.managed.len());
+        while order.len() < self.managed.len() {
+            let ready = self.managed.iter().filter(|m| remaining.get(&m.id) == Some(&0)).collect::<Vec<_>>();
+            if ready.is_empty() {
+                let cycle = self.managed.iter().filter(|m| remaining.contains_key(&m.id)).map(|m| m.name).collect();
+                return Err(LifecycleError::Cycle(cycle));
+            }
+            for managed in ready {
+                remaining.remove(&managed.id);
+                for dependent in &self.managed {
+                    if dependent.dependencies.iter().any(|(id, _)| *id == managed.id) {
+                        if let Some(count) = remaining.get_mut(&dependent.id) {
+                            *count -= 1;
+                        }
+                    }
+                }
+                order.push(managed);
+            }
+        }
+        Ok(order)
+    }
+
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+    use crate::{error::InvokerError, invoker_manager::{InvokeContext, Invoker, InvokerManager}, Typed};
+    use super::{Health, InFlight, Lifecycle, LifecycleError};
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct Service<const N: usize> {
+        log: Log,
+        in_flight: InFlight,
+        health: Health,
+    }
+
+    impl<const N: usize> Service<N> {
+        fn new(log: &Log) -> Self {
+            Self { log: log.clone(), in_flight: InFlight::default(), health: Health::Healthy }
+        }
+    }
+
+    impl<const N: usize> Invoker<()> for Service<N> {
+
+        type Res = Result<(), InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, _req: ()) -> Self::Res {
+            Ok(())
+        }
+    }
+
+    impl<const N: usize> Lifecycle for Service<N> {
+
+        fn init(&self) -> Result<(), InvokerError> {
+            self.log.lock().unwrap().push(format!("init {}", N));
+            Ok(())
+        }
+
+        fn start(&self) -> Result<(), InvokerError> {
+            self.log.lock().unwrap().push(format!("start {}", N));
+            Ok(())
+        }
+
+        fn health(&self) -> Health {
+            self.health.clone()
+        }
+
+        fn shutdown(&self, _deadline: Instant) -> Result<(), InvokerError> {
+            self.log.lock().unwrap().push(format!("shutdown {}", N));
+            Ok(())
+        }
+
+        fn in_flight(&self) -> usize {
+            self.in_flight.count()
+        }
+    }
+
+    #[test]
+    fn test_start_order() {
+        let log = Log::default();
+        let mut manager = InvokerManager::new();
+        manager.add_invoker(Service::<1>::new(&log));
+        manager.add_invoker(Service::<2>::new(&log));
+        manager.add_invoker(Service::<3>::new(&log));
+        // 1 needs 2, which needs 3
+        manager.manage::<Service<1>>().depends_on::<Service<2>>();
+        manager.manage::<Service<2>>().depends_on::<Service<3>>();
+        manager.manage::<Service<3>>();
+
+        manager.start().unwrap();
+        assert_eq!(vec!["init 3", "init 2", "init 1", "start 3", "start 2", "start 1"], *log.lock().unwrap());
+
+        log.lock().unwrap().clear();
+        assert!(manager.shutdown(Duration::from_secs(1)).unwrap().is_clean());
+        assert_eq!(vec!["shutdown 1", "shutdown 2", "shutdown 3"], *log.lock().unwrap());
+
+        manager.manage::<Service<3>>().depends_on::<Service<1>>();
+        assert!(matches!(manager.start(), Err(LifecycleError::Cycle(c)) if c.len() == 3));
+    }
+
+    #[test]
+    fn test_health_and_drain() {
+        let log = Log::default();
+        let mut manager = InvokerManager::new();
+        let mut unhealthy = Service::<2>::new(&log);
+        unhealthy.health = Health::Unhealthy("no connection".to_owned());
+        manager.add_invoker(Service::<1>::new(&log));
+        manager.add_invoker(unhealthy);
+        manager.manage::<Service<1>>();
+        manager.manage::<Service<2>>();
+
+        let report = manager.health();
+        assert!(!report.ready);
+        assert_eq!(Health::Healthy, report.invokers[0].1);
+
+        let in_flight = manager.get::<Service<1>>().unwrap().in_flight.clone();
+        let slow = in_flight.track();
+        let stuck = in_flight.track();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(slow);
+        });
+        let report = manager.shutdown(Duration::from_millis(100)).unwrap();
+        assert_eq!(vec![std::any::type_name::<Service<2>>()], report.drained);
+        assert_eq!(vec![std::any::type_name::<Service<1>>()], report.timed_out);
+        drop(stuck);
+    }
+
+}
@@ -0,0 +1,123 @@
+//! provider style member access, working on stable until `core::error::Request` lands
+//!
+//! a [`Provide`] implementor hands out references to any number of types through one
+//! [`Demand`], trait objects included, instead of being looked up by its own type
+
+use std::any::TypeId;
+
+
+/// members of a context exposing references to other types than their own
+pub trait Provide {
+
+    fn provide<'a>(&'a self, demand: &mut Demand<'a>);
+
+}
+
+
+// the concrete type behind a `Demand`, told apart by the `TypeId` of the requested type
+trait Erased<'a>: 'a {
+
+    fn tag_id(&self) -> TypeId;
+
+}
+
+struct Tagged<'a, T: ?Sized + 'static>(Option<&'a T>);
+
+impl<'a, T: ?Sized + 'static> Erased<'a> for Tagged<'a, T> {
+
+    fn tag_id(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+}
+
+
+/// request for a reference of one type, see [`request_ref`]
+#[repr(transparent)]
+pub struct Demand<'a>(dyn Erased<'a>);
+
+impl<'a> Demand<'a> {
+
+    fn new<'b>(erased: &'b mut (dyn Erased<'a> + 'a)) -> &'b mut Demand<'a> {
+        // SAFETY: `Demand` is a transparent wrapper of `dyn Erased`
+        unsafe { &mut *(erased as *mut dyn Erased<'a> as *mut Demand<'a>) }
+    }
+
+    /// answer the request with `value` if a `T` is requested and nothing answered it so far
+    pub fn provide_ref<T: ?Sized + 'static>(&mut self, value: &'a T) -> &mut Self {
+        if self.would_be_satisfied_by::<T>() {
+            // SAFETY: only `Tagged<'a, T>` answers `TypeId::of::<T>()`
+            let tagged = unsafe { &mut *(&mut self.0 as *mut dyn Erased<'a> as *mut Tagged<'a, T>) };
+            if tagged.0.is_none() {
+                tagged.0 = Some(value);
+            }
+        }
+        self
+    }
+
+    /// answer with the value returned by `f`, only called if a `T` is requested
+    pub fn provide_ref_with<T: ?Sized + 'static>(&mut self, f: impl FnOnce() -> &'a T) -> &mut Self {
+        if self.would_be_satisfied_by::<T>() {
+            self.provide_ref(f());
+        }
+        self
+    }
+
+    pub fn would_be_satisfied_by<T: ?Sized + 'static>(&self) -> bool {
+        self.0.tag_id() == TypeId::of::<T>()
+    }
+
+}
+
+
+/// a reference of type `T` provided by `provider`, e.g. `request_ref::<dyn Authenticator>(&context)`
+pub fn request_ref<'a, T: ?Sized + 'static>(provider: &'a (impl Provide + ?Sized)) -> Option<&'a T> {
+    let mut tagged = Tagged::<'a, T>(None);
+    provider.provide(Demand::new(&mut tagged));
+    tagged.0
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::{request_ref, Demand, Provide};
+
+    trait Authenticator {
+        fn user(&self) -> &str;
+    }
+
+    struct Session {
+        user: String,
+        token: String,
+    }
+
+    impl Authenticator for Session {
+        fn user(&self) -> &str {
+            &self.user
+        }
+    }
+
+    impl Provide for Session {
+
+        fn provide<'a>(&'a self, demand: &mut Demand<'a>) {
+            demand
+                .provide_ref::<dyn Authenticator>(self)
+                .provide_ref::<Session>(self)
+                .provide_ref::<str>(&self.token);
+        }
+    }
+
+    #[test]
+    fn test_request_ref() {
+        let session = Session { user: "alice".to_owned(), token: "secret".to_owned() };
+
+        assert_eq!("alice", request_ref::<dyn Authenticator>(&session).unwrap().user());
+        assert_eq!("secret", request_ref::<str>(&session).unwrap());
+        assert_eq!("alice", request_ref::<Session>(&session).unwrap().user);
+        assert!(request_ref::<String>(&session).is_none());
+
+        let provider: &dyn Provide = &session;
+        assert!(request_ref::<dyn Authenticator>(provider).is_some());
+    }
+
+}
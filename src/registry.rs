@@ -0,0 +1,336 @@
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}, sync::{Arc, Mutex, Weak}, thread, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+
+/// a remote address an invoker can be pointed at
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub address: String,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl Endpoint {
+
+    pub fn new(address: impl Into<String>) -> Self {
+        Self { address: address.into(), metadata: BTreeMap::new() }
+    }
+
+}
+
+
+pub type Listener = Box<dyn Fn(&[Endpoint]) + Send + Sync>;
+
+/// source of endpoint lists which may change at runtime
+pub trait Registry {
+
+    /// current endpoints
+    fn endpoints(&self) -> Vec<Endpoint>;
+
+    /// the listener is called with the current endpoints right away and again on every change,
+    /// until the returned subscription is dropped
+    ///
+    /// listeners are called one change at a time while the registry holds its notification
+    /// lock, they must not call back into the registry to subscribe or change the endpoints,
+    /// that deadlocks. reading [`Registry::endpoints`] is fine
+    fn subscribe(&self, listener: Listener) -> Subscription;
+
+}
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("registry io error")]
+    IoError(#[from] io::Error),
+    #[error("registry json error")]
+    JsonError(#[from] serde_json::Error),
+    #[error("registry toml error")]
+    TomlError(#[from] toml::de::Error),
+}
+
+
+#[derive(Default)]
+struct NotifierState {
+    endpoints: Vec<Endpoint>,
+    listeners: Vec<(usize, Arc<Listener>)>,
+    next_id: usize,
+}
+
+#[derive(Default)]
+struct NotifierInner {
+    // serializes notifications so listeners see changes in order
+    notify: Mutex<()>,
+    state: Mutex<NotifierState>,
+}
+
+/// endpoint list plus its subscribers, the shared part of every registry impl
+#[derive(Default, Clone)]
+pub struct Notifier {
+    inner: Arc<NotifierInner>,
+}
+
+impl Notifier {
+
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        let notifier = Self::default();
+        notifier.inner.state.lock().unwrap().endpoints = endpoints;
+        notifier
+    }
+
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.inner.state.lock().unwrap().endpoints.clone()
+    }
+
+    /// see [`Registry::subscribe`], the listener must not call back into the notifier
+    pub fn subscribe(&self, listener: Listener) -> Subscription {
+        let _guard = self.inner.notify.lock().unwrap();
+        let listener = Arc::new(listener);
+        let (id, endpoints) = {
+            let mut state = self.inner.state.lock().unwrap();
+            let id = state.next_id;
+            state.next_id += 1;
+            state.listeners.push((id, listener.clone()));
+            (id, state.endpoints.clone())
+        };
+        listener(&endpoints);
+        Subscription { id, notifier: Arc::downgrade(&self.inner) }
+    }
+
+    /// replace the endpoint list, listeners are only called when it actually changed
+    pub fn notify(&self, endpoints: Vec<Endpoint>) -> bool {
+        let _guard = self.inner.notify.lock().unwrap();
+        let listeners = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.endpoints == endpoints {
+                return false;
+            }
+            state.endpoints = endpoints.clone();
+            state.listeners.iter().map(|(_, l)| l.clone()).collect::<Vec<_>>()
+        };
+        for listener in listeners {
+            listener(&endpoints);
+        }
+        true
+    }
+
+}
+
+
+/// keeps a listener registered, dropping it unsubscribes
+pub struct Subscription {
+    id: usize,
+    notifier: Weak<NotifierInner>,
+}
+
+impl Drop for Subscription {
+
+    fn drop(&mut self) {
+        if let Some(inner) = self.notifier.upgrade() {
+            inner.state.lock().unwrap().listeners.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+
+/// registry over a fixed list, which can still be replaced by hand
+#[derive(Clone)]
+pub struct StaticRegistry {
+    notifier: Notifier,
+}
+
+impl StaticRegistry {
+
+    pub fn new(endpoints: Vec<Endpoint>) -> Self {
+        Self { notifier: Notifier::new(endpoints) }
+    }
+
+    pub fn set_endpoints(&self, endpoints: Vec<Endpoint>) -> bool {
+        self.notifier.notify(endpoints)
+    }
+
+}
+
+impl Registry for StaticRegistry {
+
+    fn endpoints(&self) -> Vec<Endpoint> {
+        self.notifier.endpoints()
+    }
+
+    fn subscribe(&self, listener: Listener) -> Subscription {
+        self.notifier.subscribe(listener)
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+struct EndpointList {
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+}
+
+struct FileRegistryInner {
+    path: PathBuf,
+    notifier: Notifier,
+    last_error: Mutex<Option<Arc<RegistryError>>>,
+}
+
+impl FileRegistryInner {
+
+    fn reload(&self) -> Result<bool, Arc<RegistryError>> {
+        match load_endpoints(&self.path) {
+            Ok(endpoints) => {
+                *self.last_error.lock().unwrap() = None;
+                Ok(self.notifier.notify(endpoints))
+            },
+            Err(e) => {
+                let e = Arc::new(e);
+                *self.last_error.lock().unwrap() = Some(e.clone());
+                Err(e)
+            },
+        }
+    }
+
+}
+
+fn load_endpoints(path: &Path) -> Result<Vec<Endpoint>, RegistryError> {
+    let content = fs::read_to_string(path)?;
+    let list: EndpointList = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        _ => serde_json::from_str(&content)?,
+    };
+    Ok(list.endpoints)
+}
+
+
+/// registry backed by a local json or toml file, standing in for consul or zookeeper
+///
+/// the file holds an `endpoints` list, the format is picked from the file extension
+pub struct FileRegistry {
+    inner: Arc<FileRegistryInner>,
+}
+
+impl FileRegistry {
+
+    /// load the endpoints from `path`, the file is only read again on [`FileRegistry::reload`]
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, RegistryError> {
+        let path = path.into();
+        let endpoints = load_endpoints(&path)?;
+        let inner = FileRegistryInner { path, notifier: Notifier::new(endpoints), last_error: Mutex::new(None) };
+        Ok(Self { inner: Arc::new(inner) })
+    }
+
+    /// load the endpoints from `path` and poll the file for changes every `interval`
+    ///
+    /// a file which can't be read or parsed keeps the last good endpoints in place, the
+    /// error is kept for [`FileRegistry::last_error`]
+    pub fn watch(path: impl Into<PathBuf>, interval: Duration) -> Result<Self, RegistryError> {
+        let registry = Self::open(path)?;
+        let weak = Arc::downgrade(&registry.inner);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match weak.upgrade() {
+                // kept for last_error
+                Some(inner) => { let _ = inner.reload(); },
+                None => break,
+            }
+        });
+        Ok(registry)
+    }
+
+    /// read the file again, returns whether the endpoints changed
+    pub fn reload(&self) -> Result<bool, Arc<RegistryError>> {
+        self.inner.reload()
+    }
+
+    /// error of the latest reload, `None` once a reload succeeded again
+    pub fn last_error(&self) -> Option<Arc<RegistryError>> {
+        self.inner.last_error.lock().unwrap().clone()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+}
+
+impl Registry for FileRegistry {
+
+    fn endpoints(&self) -> Vec<Endpoint> {
+        self.inner.notifier.endpoints()
+    }
+
+    fn subscribe(&self, listener: Listener) -> Subscription {
+        self.inner.notifier.subscribe(listener)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use std::{fs, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+    use super::{Endpoint, FileRegistry, Registry, RegistryError, StaticRegistry};
+
+    fn temp_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("invoker-explore-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_static_registry() {
+        let registry = StaticRegistry::new(vec![Endpoint::new("a:1")]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let subscription = registry.subscribe(Box::new(move |e| seen_clone.lock().unwrap().push(e.len())));
+
+        assert!(registry.set_endpoints(vec![Endpoint::new("a:1"), Endpoint::new("b:1")]));
+        // same list, no notification
+        assert!(!registry.set_endpoints(vec![Endpoint::new("a:1"), Endpoint::new("b:1")]));
+        drop(subscription);
+        registry.set_endpoints(vec![]);
+        assert_eq!(vec![1, 2], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn test_file_registry() {
+        let path = temp_file("registry.toml", "[[endpoints]]\naddress = \"a:1\"\n");
+        let registry = FileRegistry::open(&path).unwrap();
+        assert_eq!(vec![Endpoint::new("a:1")], registry.endpoints());
+
+        fs::write(&path, "[[endpoints]]\naddress = \"a:1\"\n[[endpoints]]\naddress = \"b:1\"\nmetadata = { zone = \"z1\" }\n").unwrap();
+        assert!(registry.reload().unwrap());
+        assert_eq!(2, registry.endpoints().len());
+        assert_eq!("z1", registry.endpoints()[1].metadata["zone"]);
+
+        // broken file is an error, last good endpoints stay
+        fs::write(&path, "[[endpoints]").unwrap();
+        assert!(registry.reload().is_err());
+        assert_eq!(2, registry.endpoints().len());
+        assert!(matches!(registry.last_error().as_deref(), Some(RegistryError::TomlError(_))));
+        fs::write(&path, "[[endpoints]]\naddress = \"a:1\"\n").unwrap();
+        assert!(registry.reload().unwrap());
+        assert!(registry.last_error().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_registry_watch() {
+        let path = temp_file("registry.json", r#"{"endpoints": [{"address": "a:1"}]}"#);
+        let registry = FileRegistry::watch(&path, Duration::from_millis(10)).unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let _subscription = registry.subscribe(Box::new(move |e| seen_clone.lock().unwrap().push(e.to_vec())));
+
+        fs::write(&path, r#"{"endpoints": [{"address": "b:1"}]}"#).unwrap();
+        let start = Instant::now();
+        while seen.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec![Endpoint::new("b:1")], seen.lock().unwrap()[1]);
+        fs::remove_file(path).unwrap();
+    }
+
+}
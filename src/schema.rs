@@ -0,0 +1,223 @@
+//! json schemas of [`Message`](crate::invoker::invoker5::Message) types, for the generic path
+//! through [`serde_json::Value`]
+//!
+//! schemas are derived through schemars. [`Validate`] checks requests before they are sent
+//! and responses once they are received, the [`Dispatcher`](crate::server::Dispatcher) checks
+//! requests on receipt, so bad payloads of dynamic callers never reach a handler. every
+//! violation tells the json pointer of the offending value
+
+use std::{fmt, sync::Arc};
+
+use jsonschema::JSONSchema;
+use schemars::JsonSchema;
+
+use serde_json::Value;
+
+use crate::{describe::{Describe, Description}, error::InvokerError, future::{IntoInvokerFuture, InvokerFuture}, invoker::invoker5::MethodDef, invoker_manager::{InvokeContext, Invoker}};
+
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("invalid schema: {0}")]
+    InvalidSchema(String),
+}
+
+
+/// one value not matching the schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// json pointer of the value, `/` for the root
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{}", .0.iter().map(Violation::to_string).collect::<Vec<_>>().join("; "))]
+pub struct Violations(pub Vec<Violation>);
+
+
+/// the json schema of `T` as generated by schemars
+pub fn schema_of<T: JsonSchema>() -> Value {
+    serde_json::to_value(schemars::schema_for!(T)).expect("schemas are plain json")
+}
+
+
+/// a compiled json schema
+#[derive(Clone)]
+pub struct Schema {
+    json: Arc<Value>,
+    compiled: Arc<JSONSchema>,
+}
+
+impl fmt::Debug for Schema {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Schema").field(&self.json).finish()
+    }
+}
+
+impl Schema {
+
+    pub fn new(json: Value) -> Result<Self, SchemaError> {
+        let compiled = JSONSchema::compile(&json).map_err(|e| SchemaError::InvalidSchema(e.to_string()))?;
+        Ok(Self { json: Arc::new(json), compiled: Arc::new(compiled) })
+    }
+
+    pub fn of<T: JsonSchema>() -> Self {
+        Self::new(schema_of::<T>()).expect("schemars generates valid schemas")
+    }
+
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+
+    pub fn validate(&self, value: &Value) -> Result<(), Violations> {
+        self.compiled.validate(value).map_err(|errors| {
+            Violations(errors.map(|e| {
+                let path = e.instance_path.to_string();
+                let path = if path.is_empty() { "/".to_owned() } else { path };
+                Violation { path, message: e.to_string() }
+            }).collect())
+        })
+    }
+
+}
+
+
+/// validates requests of the generic path before they are sent, and responses once received
+///
+/// bad payloads fail with [`InvokerError::InvalidPayload`], the inner invoker may answer
+/// synchronously or not
+pub struct Validate<I> {
+    inner: I,
+    request: Schema,
+    response: Schema,
+}
+
+impl<I> Validate<I> {
+
+    pub fn new(inner: I, request: Schema, response: Schema) -> Self {
+        Self { inner, request, response }
+    }
+
+    /// with the schemas of the request and response of `M`
+    pub fn of<M>(inner: I) -> Self
+    where
+        M: MethodDef,
+        M::Request: JsonSchema,
+        M::Response: JsonSchema,
+    {
+        Self::new(inner, Schema::of::<M::Request>(), Schema::of::<M::Response>())
+    }
+
+}
+
+impl<I> Invoker<Value> for Validate<I>
+where
+    I: Invoker<Value>,
+    I::Res: IntoInvokerFuture<Value>
+{
+
+    type Res = InvokerFuture<Value>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Value) -> Self::Res {
+        if let Err(violations) = self.request.validate(&req) {
+            return InvokerFuture::ready(Err(InvokerError::InvalidPayload(violations)));
+        }
+        let res = self.inner.invoke(context, req).into_invoker_future();
+        let response = self.response.clone();
+        InvokerFuture::new(async move {
+            let res = res.await?;
+            response.validate(&res).map_err(InvokerError::InvalidPayload)?;
+            Ok(res)
+        })
+    }
+}
+
+impl<I: Describe> Describe for Validate<I> {
+
+    fn describe(&self) -> Description {
+        self.inner.describe().layer("validate")
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use futures_executor::block_on;
+    use schemars::JsonSchema;
+    use serde_json::{json, Value};
+
+    use crate::{error::InvokerError, invoker_manager::{InvokeContext, Invoker}};
+    use super::{Schema, Validate};
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Order {
+        id: u64,
+        items: Vec<Item>,
+        note: Option<String>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Item {
+        sku: String,
+        quantity: u32,
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = Schema::of::<Order>();
+        assert!(schema.validate(&json!({"id": 1, "items": [{"sku": "a", "quantity": 2}]})).is_ok());
+
+        let violations = schema.validate(&json!({"id": "1", "items": [{"sku": "a", "quantity": -2}]})).unwrap_err();
+        let mut paths = violations.0.iter().map(|v| v.path.as_str()).collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(vec!["/id", "/items/0/quantity"], paths);
+
+        let violations = schema.validate(&json!({"items": []})).unwrap_err();
+        assert_eq!("/", violations.0[0].path);
+        assert!(violations.to_string().contains("id"));
+    }
+
+    /// answers with `{"id": ..}` of the request
+    struct Echo;
+
+    impl Invoker<Value> for Echo {
+
+        type Res = Result<Value, InvokerError>;
+
+        fn invoke(&self, _context: &mut InvokeContext, req: Value) -> Self::Res {
+            Ok(json!({"id": req["id"]}))
+        }
+    }
+
+    #[test]
+    fn test_validate_invoker() {
+        let response = Schema::new(json!({"type": "object", "properties": {"id": {"type": "integer"}}})).unwrap();
+        let invoker = Validate::new(Echo, Schema::of::<Order>(), response);
+
+        let res = block_on(invoker.invoke(&mut InvokeContext::new(), json!({"id": 1, "items": []}))).unwrap();
+        assert_eq!(json!({"id": 1}), res);
+
+        // never sent
+        let res = block_on(invoker.invoke(&mut InvokeContext::new(), json!({"id": 1})));
+        assert!(matches!(res, Err(InvokerError::InvalidPayload(_))));
+
+        let invoker = Validate::new(Echo, Schema::new(json!({})).unwrap(), Schema::of::<Order>());
+        match block_on(invoker.invoke(&mut InvokeContext::new(), json!({"id": 1}))) {
+            Err(InvokerError::InvalidPayload(violations)) => assert_eq!("/", violations.0[0].path),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+}
@@ -0,0 +1,391 @@
+//! server side dispatch of generic calls by service and method name
+//!
+//! a [`Dispatcher`] routes [`Value`] requests to the handlers of its [`Service`]s. it always
+//! serves [`ServerReflection`] as well, listing every service and method along with its
+//! [`MethodDefInfo`] and the json schemas of request and response, so generic clients and
+//! cli tools can discover what to call. requests violating the schema of their method are
+//! rejected before they reach the handler
+//!
+//! handlers are invokers like any other, layers and [`handler`](crate::handler::handler)
+//! functions included. they are invoked with the context of the call, holding the
+//! [`Method`] being served along with what the transport put there, like [`Metadata`](crate::context::Metadata)
+
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{context::Method, describe::MethodDescription, error::InvokerError, future::{IntoInvokerFuture, InvokerFuture}, invoker::invoker5::{MethodDef, MethodDefInfo}, invoker_manager::{InvokeContext, Invoker}, schema::{Schema, Violation, Violations}};
+
+
+/// the service [`ServerReflection`] is served under
+pub const REFLECTION_SERVICE: &str = "reflection";
+
+
+/// lists the services of the [`Dispatcher`], takes a [`ReflectionRequest`] and answers a
+/// [`ReflectionResponse`]
+#[derive(Debug, Clone, Default)]
+pub struct ServerReflection {
+    info: MethodDefInfo,
+}
+
+impl MethodDef for ServerReflection {
+
+    const NAME: &'static str = "serverReflection";
+
+    type Request = Value;
+
+    type Response = Value;
+
+    fn get_method_def_info(&self) -> &MethodDefInfo {
+        &self.info
+    }
+}
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReflectionRequest {
+    /// only this service, all of them if `None`
+    #[serde(default)]
+    pub service: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReflectionResponse {
+    pub services: Vec<ServiceDescriptor>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceDescriptor {
+    pub name: String,
+    pub methods: Vec<MethodDescriptor>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MethodDescriptor {
+    #[serde(flatten)]
+    pub description: MethodDescription,
+    pub info: MethodDefInfo,
+}
+
+
+type BoxHandler = Box<dyn Invoker<Value, Res = InvokerFuture<Value>> + Send + Sync>;
+
+// answers sync handlers with a future as well
+struct Responding<H>(H);
+
+impl<H> Invoker<Value> for Responding<H>
+where
+    H: Invoker<Value>,
+    H::Res: IntoInvokerFuture<Value>
+{
+
+    type Res = InvokerFuture<Value>;
+
+    fn invoke(&self, context: &mut InvokeContext, req: Value) -> Self::Res {
+        self.0.invoke(context, req).into_invoker_future()
+    }
+}
+
+enum Handler {
+    Invoker(BoxHandler),
+    // answered by the dispatcher, which knows all services
+    Reflection,
+}
+
+struct MethodEntry {
+    descriptor: MethodDescriptor,
+    method: Method,
+    request: Schema,
+    handler: Handler,
+}
+
+
+/// methods served under one name
+pub struct Service {
+    name: String,
+    methods: BTreeMap<&'static str, MethodEntry>,
+}
+
+impl Service {
+
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), methods: BTreeMap::new() }
+    }
+
+    /// serve `M` with `handler`, `def` tells its [`MethodDefInfo`]
+    pub fn method<M, H>(self, def: M, handler: H) -> Self
+    where
+        M: MethodDef,
+        M::Request: JsonSchema,
+        M::Response: JsonSchema,
+        H: Invoker<Value> + Send + Sync + 'static,
+        H::Res: IntoInvokerFuture<Value>,
+    {
+        self.entry(def, Handler::Invoker(Box::new(Responding(handler))))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn entry<M>(mut self, def: M, handler: Handler) -> Self
+    where
+        M: MethodDef,
+        M::Request: JsonSchema,
+        M::Response: JsonSchema,
+    {
+        let descriptor = MethodDescriptor {
+            description: MethodDescription::with_schemas::<M>(),
+            info: def.get_method_def_info().clone(),
+        };
+        let entry = MethodEntry { descriptor, method: Method::of::<M>(), request: Schema::of::<M::Request>(), handler };
+        self.methods.insert(M::NAME, entry);
+        self
+    }
+
+    fn descriptor(&self) -> ServiceDescriptor {
+        ServiceDescriptor {
+            name: self.name.clone(),
+            methods: self.methods.values().map(|m| m.descriptor.clone()).collect(),
+        }
+    }
+
+}
+
+
+pub struct Dispatcher {
+    services: BTreeMap<String, Service>,
+}
+
+impl Default for Dispatcher {
+
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dispatcher {
+
+    pub fn new() -> Self {
+        let reflection = Service::new(REFLECTION_SERVICE).entry(ServerReflection::default(), Handler::Reflection);
+        let mut services = BTreeMap::new();
+        services.insert(reflection.name.clone(), reflection);
+        Self { services }
+    }
+
+    /// replaces the service of the same name
+    pub fn add_service(&mut self, service: Service) -> Option<Service> {
+        self.services.insert(service.name.clone(), service)
+    }
+
+    /// invoke the handler of `service` and `method` with `context`, which is given the
+    /// [`Method`] of the handler while the handler is invoked
+    pub fn dispatch(&self, context: &mut InvokeContext, service: &str, method: &str, req: Value) -> InvokerFuture<Value> {
+        let entry = match self.services.get(service).and_then(|s| s.methods.get(method)) {
+            Some(entry) => entry,
+            None => {
+                let err = InvokerError::NotFound { service: service.to_string(), method: method.to_string() };
+                return InvokerFuture::ready(Err(err));
+            },
+        };
+        if let Err(violations) = entry.request.validate(&req) {
+            return InvokerFuture::ready(Err(InvokerError::InvalidPayload(violations)));
+        }
+        let previous = context.insert(entry.method);
+        let res = match &entry.handler {
+            Handler::Invoker(invoker) => invoker.invoke(context, req),
+            Handler::Reflection => InvokerFuture::ready(self.reflect(req)),
+        };
+        match previous {
+            Some(previous) => { context.insert(previous); },
+            None => { context.remove::<Method>(); },
+        }
+        res
+    }
+
+    /// what [`ServerReflection`] answers
+    pub fn reflection(&self, req: &ReflectionRequest) -> Result<ReflectionResponse, InvokerError> {
+        let services = match &req.service {
+            Some(name) => {
+                let service = self.services.get(name)
+                    .ok_or_else(|| InvokerError::NotFound { service: name.clone(), method: "*".to_string() })?;
+                vec![service.descriptor()]
+            },
+            None => self.services.values().map(Service::descriptor).collect(),
+        };
+        Ok(ReflectionResponse { services })
+    }
+
+    fn reflect(&self, req: Value) -> Result<Value, InvokerError> {
+        // an empty request lists everything
+        let req = match req {
+            Value::Null => ReflectionRequest::default(),
+            req => serde_json::from_value(req).map_err(|e| {
+                InvokerError::InvalidPayload(Violations(vec![Violation { path: "/".to_owned(), message: e.to_string() }]))
+            })?,
+        };
+        Ok(serde_json::to_value(self.reflection(&req)?).map_err(anyhow::Error::from)?)
+    }
+
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use futures_executor::block_on;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::{
+        context::{Metadata, Method},
+        error::InvokerError,
+        future::InvokerFuture,
+        handler::handler,
+        invoker::invoker5::{JsonDecoder, JsonEncoder, Message, MethodDef, MethodDefInfo},
+        invoker_manager::InvokeContext,
+        layer::rate_limit::{RateLimit, RateLimitConfig, RateLimitMode},
+        Typed,
+    };
+    use super::{Dispatcher, ServerReflection, Service, REFLECTION_SERVICE};
+
+    struct Echo {
+        info: MethodDefInfo,
+    }
+
+    impl MethodDef for Echo {
+
+        const NAME: &'static str = "echo";
+
+        type Request = Value;
+
+        type Response = Value;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+    fn echo(req: Value) -> InvokerFuture<Value> {
+        InvokerFuture::new(async move { Ok(req) })
+    }
+
+    fn dispatcher() -> Dispatcher {
+        let info = MethodDefInfo { idempotent: true, ..MethodDefInfo::default() };
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.add_service(Service::new("echo").method(Echo { info }, handler(echo)));
+        dispatcher
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let dispatcher = dispatcher();
+        let res = block_on(dispatcher.dispatch(&mut InvokeContext::new(), "echo", "echo", json!({"a": 1}))).unwrap();
+        assert_eq!(json!({"a": 1}), res);
+
+        let res = block_on(dispatcher.dispatch(&mut InvokeContext::new(), "echo", "missing", Value::Null));
+        assert!(matches!(res, Err(InvokerError::NotFound { .. })));
+
+        // the caller's method is back once the call is dispatched
+        let mut context = InvokeContext::new();
+        context.with_context(Method::named("outer"));
+        block_on(dispatcher.dispatch(&mut context, "echo", "echo", Value::Null)).unwrap();
+        assert_eq!(Some(&Method::named("outer")), context.get::<Method>());
+        let mut context = InvokeContext::new();
+        block_on(dispatcher.dispatch(&mut context, "echo", "echo", Value::Null)).unwrap();
+        assert!(!context.contains::<Method>());
+    }
+
+    #[test]
+    fn test_reflection() {
+        let dispatcher = dispatcher();
+        let reflect = |req: Value| block_on(dispatcher.dispatch(&mut InvokeContext::new(), REFLECTION_SERVICE, ServerReflection::NAME, req));
+        let res = reflect(Value::Null).unwrap();
+        let services = res["services"].as_array().unwrap();
+        assert_eq!(2, services.len());
+
+        let echo = services.iter().find(|s| s["name"] == "echo").unwrap();
+        assert_eq!("echo", echo["methods"][0]["name"]);
+        assert_eq!(true, echo["methods"][0]["info"]["idempotent"]);
+        assert!(echo["methods"][0]["request_schema"].is_object());
+
+        let res = reflect(json!({"service": "echo"})).unwrap();
+        assert_eq!(1, res["services"].as_array().unwrap().len());
+
+        assert!(matches!(reflect(json!({"service": "nope"})), Err(InvokerError::NotFound { .. })));
+        assert!(matches!(dispatcher.reflect(json!({"service": 1})), Err(InvokerError::InvalidPayload(_))));
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    struct Greeting {
+        name: String,
+    }
+
+    impl Message for Greeting {
+        type MsgType = String;
+        type Encoder = JsonEncoder;
+        type Decoder = JsonDecoder<Self>;
+    }
+
+    #[derive(Default)]
+    struct Greet {
+        info: MethodDefInfo,
+    }
+
+    impl MethodDef for Greet {
+
+        const NAME: &'static str = "greet";
+
+        type Request = Greeting;
+
+        type Response = Greeting;
+
+        fn get_method_def_info(&self) -> &MethodDefInfo {
+            &self.info
+        }
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.add_service(Service::new("greeter").method(Greet::default(), handler(echo)));
+
+        let res = block_on(dispatcher.dispatch(&mut InvokeContext::new(), "greeter", "greet", json!({"name": "bob"})));
+        assert!(res.is_ok());
+        match block_on(dispatcher.dispatch(&mut InvokeContext::new(), "greeter", "greet", json!({"name": 1}))) {
+            Err(InvokerError::InvalidPayload(violations)) => assert_eq!("/name", violations.0[0].path),
+            res => panic!("unexpected {:?}", res),
+        }
+    }
+
+    fn greet(method: Method, metadata: Metadata, req: Value) -> Result<Value, InvokerError> {
+        let lang = metadata.get("lang").unwrap_or("en").to_owned();
+        Ok(json!({"name": format!("{} {} {}", method.name(), lang, req["name"].as_str().unwrap_or_default())}))
+    }
+
+    #[test]
+    fn test_layered_handler() {
+        let config = RateLimitConfig { rate: 0.001, burst: 1, mode: RateLimitMode::Reject };
+        let limited = RateLimit::by_metadata(handler(greet), config, "api-key").unwrap();
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.add_service(Service::new("greeter").method(Greet::default(), limited));
+
+        let mut metadata = Metadata::new();
+        metadata.insert("api-key", "k1");
+        metadata.insert("lang", "fr");
+        let mut context = InvokeContext::new();
+        context.with_context(metadata);
+        let res = block_on(dispatcher.dispatch(&mut context, "greeter", "greet", json!({"name": "bob"}))).unwrap();
+        assert_eq!(json!({"name": "greet fr bob"}), res);
+
+        let res = block_on(dispatcher.dispatch(&mut context, "greeter", "greet", json!({"name": "bob"})));
+        assert!(matches!(res, Err(InvokerError::RateLimited(key)) if key == "k1"));
+        // the handler is not reached without its metadata
+        let res = block_on(dispatcher.dispatch(&mut InvokeContext::new(), "greeter", "greet", json!({"name": "bob"})));
+        assert!(matches!(res, Err(InvokerError::MissingContext(_))));
+    }
+
+}
@@ -0,0 +1,110 @@
+//! minimal timer so the async layers don't depend on a runtime
+
+use std::{cmp::Reverse, collections::BinaryHeap, future::Future, pin::Pin, sync::{mpsc, Arc, Mutex}, task::{Context, Poll, Waker}, thread, time::Instant};
+
+use once_cell::sync::Lazy;
+
+
+#[derive(Default)]
+struct DelayState {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+struct Timer {
+    at: Instant,
+    state: Arc<Mutex<DelayState>>,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+// one thread fires every delay, woken up early whenever a new timer is added
+static TIMERS: Lazy<Mutex<mpsc::Sender<Timer>>> = Lazy::new(|| {
+    let (tx, rx) = mpsc::channel::<Timer>();
+    thread::Builder::new().name("invoker-timer".to_owned()).spawn(move || {
+        let mut timers = BinaryHeap::new();
+        loop {
+            let next = timers.peek().map(|t: &Reverse<Timer>| t.0.at);
+            let received = match next {
+                Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())).map_err(|e| e == mpsc::RecvTimeoutError::Disconnected),
+                None => rx.recv().map_err(|_| true),
+            };
+            match received {
+                Ok(timer) => timers.push(Reverse(timer)),
+                Err(true) => break,
+                Err(false) => {},
+            }
+            let now = Instant::now();
+            while timers.peek().map(|t| t.0.at <= now).unwrap_or(false) {
+                let timer = timers.pop().unwrap().0;
+                let mut state = timer.state.lock().unwrap();
+                state.done = true;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }).unwrap();
+    Mutex::new(tx)
+});
+
+
+/// future completing at the given instant
+pub struct Delay {
+    at: Instant,
+    state: Option<Arc<Mutex<DelayState>>>,
+}
+
+impl Delay {
+
+    pub fn until(at: Instant) -> Self {
+        Self { at, state: None }
+    }
+
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+
+}
+
+impl Future for Delay {
+
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Instant::now() >= self.at {
+            return Poll::Ready(());
+        }
+        // the timer is only registered once the delay is polled
+        let at = self.at;
+        let state = self.state.get_or_insert_with(|| {
+            let state = Arc::new(Mutex::new(DelayState::default()));
+            let _ = TIMERS.lock().unwrap().send(Timer { at, state: state.clone() });
+            state
+        });
+        let mut state = state.lock().unwrap();
+        if state.done {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}